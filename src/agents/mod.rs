@@ -1,13 +1,15 @@
 use crate::config::Config;
-use crate::llm_provider::{LLMProvider, Message, Provider};
+use crate::llm_provider::{self, LLMProvider, Message, Provider};
+use crate::migrate::{self, Schema};
+use crate::project::{self, ProjectConfig};
+use crate::registry::RegistryConfig;
 use crate::utils;
-use anyhow::Result;
+use anyhow::{Context, Result};
 use colored::*;
 use futures::StreamExt;
-use serde_json;
 use std::fs;
 use std::io::{self, Write};
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
 use tokio::select;
@@ -47,6 +49,21 @@ impl ConversationContext {
     }
 }
 
+/// 当前操作系统的提示词，shell 为 None 时使用系统默认的 shell
+/// 其他系统（如 FreeBSD）使用 sh 作为默认 shell
+pub fn platform_prompt(shell: Option<&str>) -> String {
+    let (os, default_shell) = if cfg!(target_os = "windows") {
+        ("windows", "powershell")
+    } else if cfg!(target_os = "macos") {
+        ("macos", "zsh")
+    } else if cfg!(target_os = "linux") {
+        ("linux", "bash")
+    } else {
+        (std::env::consts::OS, "sh")
+    };
+    format!("你现在处于{}系统，请使用{}命令（{}）\n", os, os, shell.unwrap_or(default_shell))
+}
+
 impl Agent {
     /* 构建系统提示词 */
     fn build_system_prompt(&self) -> String {
@@ -66,13 +83,7 @@ impl Agent {
         prompt.push_str("3. 如果需要使用多个命令，将它们组合在一起\n");
         prompt.push_str("4. 确保命令的语法正确\n\n");
 
-        if cfg!(target_os = "windows") {
-            prompt.push_str("你现在处于windows系统，请使用windows命令（powershell）\n");
-        } else if cfg!(target_os = "macos") {
-            prompt.push_str("你现在处于macos系统，请使用macos命令（zsh）\n");
-        } else if cfg!(target_os = "linux") {
-            prompt.push_str("你现在处于linux系统，请使用linux命令（bash）\n");
-        }
+        prompt.push_str(&platform_prompt(None));
         
         // 添加环境变量信息
         if !self.env.is_empty() {
@@ -127,10 +138,7 @@ impl Agent {
             .ok_or_else(|| anyhow::anyhow!("未配置模型"))?;
 
         // 创建 provider
-        let provider = Provider::new(model_config.api_key.clone())
-            .with_url(model_config.api_url.clone())
-            .with_model(model_config.model.clone())
-            .with_json_mode(true);
+        let provider = config.provider(model_config)?.with_json_mode(true);
 
         let mut context = ConversationContext::new(provider, running);
 
@@ -194,7 +202,7 @@ impl Agent {
             }
         };
 
-        let mut stream = stream_result?;
+        let mut stream = llm_provider::text_stream(stream_result?);
         let mut response = String::new();
         let mut is_json_complete = false;
 
@@ -205,21 +213,20 @@ impl Agent {
             }
 
             match result {
-                Ok(content) if !content.is_empty() => {
+                Ok(content) => {
                     response.push_str(&content);
-                    if !is_json_complete {
-                        if let Ok(_) = serde_json::from_str::<serde_json::Value>(&response) {
-                            is_json_complete = true;
-                            print!("{}", response.green());
-                            io::stdout().flush()?;
-                        }
+                    if !is_json_complete
+                        && serde_json::from_str::<serde_json::Value>(&response).is_ok()
+                    {
+                        is_json_complete = true;
+                        print!("{}", response.green());
+                        io::stdout().flush()?;
                     }
                 }
                 Err(e) => {
-                    eprintln!("\n错误: {}", e);
-                    break;
+                    println!();
+                    return Err(e);
                 }
-                _ => {}
             }
         }
 
@@ -235,31 +242,59 @@ impl Agent {
 impl AgentManager {
     pub fn load() -> Result<Self> {
         let mut manager = Self::new();
+
+        // registry 中的 agent 名称带命名空间，例如 team/deploy
+        for (name, agent) in RegistryConfig::available_agents() {
+            manager.load_agent(&name, agent);
+        }
         
         if let Ok(agents_dir) = Self::get_agents_dir() {
-            if agents_dir.exists() {
-                for entry in fs::read_dir(agents_dir)? {
-                    let entry = entry?;
-                    let path = entry.path();
-                    if path.extension().map_or(false, |ext| ext == "toml") {
-                        let content = fs::read_to_string(&path)?;
-                        let agent: Agent = toml::from_str(&content)?;
-                        let name = path.file_stem().unwrap().to_string_lossy().to_string();
-                        manager.load_agent(&name, agent);
-                    }
-                }
+            manager.load_dir(&agents_dir)?;
+        }
+
+        // 项目级 agent 不能替换同名的用户级 agent
+        if let Some(mut project) = ProjectConfig::discover() {
+            let mut agents = std::mem::take(&mut project.agents);
+            project::drop_shadowed(&project, "agent", &mut agents, |name| manager.agents.contains_key(name));
+            for (name, agent) in agents {
+                manager.load_agent(&name, agent);
             }
         }
         
         Ok(manager)
     }
 
+    /// 加载目录中的所有 `<name>.toml` agent 配置，目录不存在时忽略
+    /// 只有用户 agent 目录中的旧版本文件会被原地升级
+    pub fn load_dir(&mut self, dir: &Path) -> Result<()> {
+        if !dir.exists() {
+            return Ok(());
+        }
+        let persist = Self::get_agents_dir().is_ok_and(|agents_dir| agents_dir == dir);
+        // 原地升级时与 save_agent 共用目录锁
+        let _lock = if persist { Some(utils::lock_file(dir)?) } else { None };
+        for entry in fs::read_dir(dir)? {
+            let entry = entry?;
+            let path = entry.path();
+            if path.extension().is_some_and(|ext| ext == "toml") {
+                let content = fs::read_to_string(&path)?;
+                let content = migrate::upgrade(Schema::Agent, &path, &content, persist)?;
+                let agent: Agent = toml::from_str(&content)
+                    .with_context(|| format!("invalid agent file: {}", path.display()))?;
+                let name = path.file_stem().unwrap().to_string_lossy().to_string();
+                self.load_agent(&name, agent);
+            }
+        }
+        Ok(())
+    }
+
     pub fn save_agent(&self, name: &str, agent: &Agent) -> Result<()> {
         let path = Self::get_agents_dir()?;
         fs::create_dir_all(&path)?;
-        let mut file_path = path;
-        file_path.push(format!("{}.toml", name));
+        let file_path = path.join(format!("{}.toml", name));
         let content = toml::to_string_pretty(agent)?;
+        // 锁住整个 agents 目录（agents.lock），避免并发写入同一个 agent
+        let _lock = utils::lock_file(&path)?;
         utils::save_file(&content, &file_path)?;
         Ok(())
     }
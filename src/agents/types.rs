@@ -4,6 +4,10 @@ use serde::{Serialize, Deserialize};
 /// Agent 配置
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Agent {
+    /// 配置文件格式版本
+    #[serde(default)]
+    pub version: u32,
+
     /// Agent 名称
     pub name: String,
     
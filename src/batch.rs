@@ -0,0 +1,513 @@
+use crate::bots::Bot;
+use crate::cache::ResponseCache;
+use crate::config::Config;
+use crate::llm_provider::{self, ChatEvent, GenerationParams, LLMProvider, Message, Provider, ProviderError, Usage};
+use crate::template::{self, TemplateContext};
+use anyhow::{Context, Result};
+use colored::*;
+use futures::StreamExt;
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::fs::{self, OpenOptions};
+use std::io::{IsTerminal, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// 进度条的宽度
+const BAR_WIDTH: usize = 30;
+
+/// 输入文件格式
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum InputFormat {
+    Jsonl,
+    Csv,
+}
+
+impl InputFormat {
+    /// 根据扩展名判断格式，.csv 以外的文件都按 JSONL 读取
+    pub fn detect(path: &Path) -> Self {
+        match path.extension().and_then(|ext| ext.to_str()) {
+            Some(ext) if ext.eq_ignore_ascii_case("csv") => InputFormat::Csv,
+            _ => InputFormat::Jsonl,
+        }
+    }
+}
+
+impl std::str::FromStr for InputFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "jsonl" => Ok(InputFormat::Jsonl),
+            "csv" => Ok(InputFormat::Csv),
+            _ => Err(anyhow::anyhow!("unknown input format: {} (expected jsonl or csv)", s)),
+        }
+    }
+}
+
+/// 批处理参数
+pub struct BatchOptions<'a> {
+    pub input: PathBuf,
+    pub output: PathBuf,
+    pub format: InputFormat,
+    /// 使用的模型，未设置时使用机器人固定的模型或当前模型
+    pub model: Option<String>,
+    pub bot: Option<&'a Bot>,
+    /// 用户消息模板，引用输入中的列，例如 "Translate to {{lang}}: {{text}}"
+    pub template: Option<String>,
+    pub concurrency: usize,
+    /// 限流、服务端或网络错误时每条提示词额外重试的次数
+    pub retries: u32,
+    pub params: GenerationParams,
+    pub template_ctx: TemplateContext,
+}
+
+/// 输入中的一行
+struct Row {
+    id: String,
+    columns: Vec<(String, String)>,
+}
+
+/// 输出文件中的一行，error 为空表示已完成
+#[derive(Debug, Serialize, Deserialize)]
+struct BatchResult {
+    id: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    output: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    finish_reason: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    usage: Option<Usage>,
+    #[serde(default)]
+    latency_ms: u64,
+    #[serde(default)]
+    attempts: u32,
+}
+
+/// JSON 值转换为模板变量，字符串不带引号
+fn column_value(value: &serde_json::Value) -> String {
+    match value {
+        serde_json::Value::String(text) => text.clone(),
+        serde_json::Value::Null => String::new(),
+        other => other.to_string(),
+    }
+}
+
+fn read_jsonl(content: &str) -> Result<Vec<Row>> {
+    let mut rows = Vec::new();
+    for (i, line) in content.lines().enumerate() {
+        if line.trim().is_empty() {
+            continue;
+        }
+        let value: serde_json::Value = serde_json::from_str(line)
+            .with_context(|| format!("line {}: invalid JSON", i + 1))?;
+        let object = value
+            .as_object()
+            .ok_or_else(|| anyhow::anyhow!("line {}: expected a JSON object", i + 1))?;
+        let columns: Vec<(String, String)> = object
+            .iter()
+            .map(|(key, value)| (key.clone(), column_value(value)))
+            .collect();
+        let id = object
+            .get("id")
+            .map(column_value)
+            .unwrap_or_else(|| (i + 1).to_string());
+        rows.push(Row { id, columns });
+    }
+    Ok(rows)
+}
+
+/// 解析 CSV，支持引号包裹的字段、字段内的逗号、换行与转义的双引号
+fn parse_csv(content: &str) -> Result<Vec<Vec<String>>> {
+    let mut records = Vec::new();
+    let mut record = Vec::new();
+    let mut field = String::new();
+    let mut quoted = false;
+    let mut chars = content.trim_start_matches('\u{feff}').chars().peekable();
+    while let Some(c) = chars.next() {
+        if quoted {
+            match c {
+                '"' if chars.peek() == Some(&'"') => {
+                    field.push('"');
+                    chars.next();
+                }
+                '"' => quoted = false,
+                _ => field.push(c),
+            }
+            continue;
+        }
+        match c {
+            '"' if field.is_empty() => quoted = true,
+            ',' => record.push(std::mem::take(&mut field)),
+            '\r' if chars.peek() == Some(&'\n') => {}
+            '\n' => {
+                record.push(std::mem::take(&mut field));
+                records.push(std::mem::take(&mut record));
+            }
+            _ => field.push(c),
+        }
+    }
+    if quoted {
+        return Err(anyhow::anyhow!("unterminated quoted field"));
+    }
+    if !field.is_empty() || !record.is_empty() {
+        record.push(field);
+        records.push(record);
+    }
+    // 忽略空行
+    records.retain(|record| !(record.len() == 1 && record[0].trim().is_empty()));
+    Ok(records)
+}
+
+fn read_csv(content: &str) -> Result<Vec<Row>> {
+    let mut records = parse_csv(content)?.into_iter();
+    let Some(header) = records.next() else {
+        return Ok(Vec::new());
+    };
+    let header: Vec<String> = header.into_iter().map(|name| name.trim().to_string()).collect();
+    let mut rows = Vec::new();
+    for (i, record) in records.enumerate() {
+        if record.len() != header.len() {
+            return Err(anyhow::anyhow!(
+                "row {}: expected {} column(s), found {}",
+                i + 1,
+                header.len(),
+                record.len()
+            ));
+        }
+        let columns: Vec<(String, String)> = header.iter().cloned().zip(record).collect();
+        let id = columns
+            .iter()
+            .find(|(name, _)| name == "id")
+            .map(|(_, value)| value.clone())
+            .unwrap_or_else(|| (i + 1).to_string());
+        rows.push(Row { id, columns });
+    }
+    Ok(rows)
+}
+
+fn read_rows(path: &Path, format: InputFormat) -> Result<Vec<Row>> {
+    let content = fs::read_to_string(path)
+        .with_context(|| format!("can not read input file {}", path.display()))?;
+    let rows = match format {
+        InputFormat::Jsonl => read_jsonl(&content),
+        InputFormat::Csv => read_csv(&content),
+    }
+    .with_context(|| path.display().to_string())?;
+
+    let mut ids = HashSet::new();
+    for row in &rows {
+        if !ids.insert(row.id.as_str()) {
+            return Err(anyhow::anyhow!("{}: duplicate id '{}'", path.display(), row.id));
+        }
+    }
+    Ok(rows)
+}
+
+/// 读取已有的输出文件，返回已成功完成的 id
+/// 中断时可能留下写了一半的最后一行，无法解析的行会被忽略
+fn completed_ids(path: &Path) -> Result<HashSet<String>> {
+    let content = match fs::read_to_string(path) {
+        Ok(content) => content,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(HashSet::new()),
+        Err(e) => return Err(e).with_context(|| format!("can not read output file {}", path.display())),
+    };
+    let mut done = HashSet::new();
+    for line in content.lines() {
+        let Ok(result) = serde_json::from_str::<BatchResult>(line) else {
+            continue;
+        };
+        // 同一个 id 以最后一次的结果为准
+        if result.error.is_none() {
+            done.insert(result.id);
+        } else {
+            done.remove(&result.id);
+        }
+    }
+    Ok(done)
+}
+
+/// 根据输入行生成请求消息
+fn build_messages(config: &Config, options: &BatchOptions, row: &Row) -> Result<Vec<Message>> {
+    let ctx = row
+        .columns
+        .iter()
+        .fold(options.template_ctx.clone(), |ctx, (name, value)| ctx.with_var(name, value));
+    let input = match &options.template {
+        Some(template) => template::render(template, &ctx)?,
+        None => row
+            .columns
+            .iter()
+            .find(|(name, _)| name == "prompt" || name == "input")
+            .map(|(_, value)| value.clone())
+            .ok_or_else(|| anyhow::anyhow!("no 'prompt' or 'input' column, use --template to build the prompt"))?,
+    };
+
+    let mut messages = Vec::new();
+    match options.bot {
+        Some(bot) => {
+            messages.extend(bot.initial_messages(&ctx)?);
+            messages.push(Message::new("user", bot.render_input(&input, &ctx)?));
+        }
+        None => {
+            if let Some(system_prompt) = config.get_system_prompt() {
+                messages.push(Message::new("system", system_prompt.clone()));
+            }
+            messages.push(Message::new("user", input));
+        }
+    }
+    Ok(messages)
+}
+
+/// 发送一条请求，可重试的错误按退避时间重试
+async fn process(provider: &Provider, id: String, messages: Vec<Message>, retries: u32, running: Arc<AtomicBool>) -> BatchResult {
+    let start = Instant::now();
+    let mut result = BatchResult {
+        id,
+        output: None,
+        error: None,
+        finish_reason: None,
+        usage: None,
+        latency_ms: 0,
+        attempts: 0,
+    };
+    loop {
+        result.attempts += 1;
+        let attempt: Result<()> = async {
+            let mut events = provider.chat(messages.clone(), false, running.clone()).await?;
+            let mut output = String::new();
+            while let Some(event) = events.next().await {
+                match event {
+                    ChatEvent::TextDelta(text) => output.push_str(&text),
+                    ChatEvent::Usage(usage) => result.usage = Some(usage),
+                    ChatEvent::Finish(reason) => result.finish_reason = Some(reason.to_string()),
+                    ChatEvent::Error(e) => return Err(e.into()),
+                    ChatEvent::ReasoningDelta(_) | ChatEvent::ToolCallDelta(_) => {}
+                }
+            }
+            result.output = Some(output);
+            Ok(())
+        }
+        .await;
+        match attempt {
+            Ok(()) => {
+                result.error = None;
+                break;
+            }
+            Err(e) => {
+                let retry = e
+                    .downcast_ref::<ProviderError>()
+                    .filter(|error| error.is_retryable() && result.attempts <= retries)
+                    .map(|error| error.retry_delay(result.attempts - 1));
+                result.error = Some(format!("{:#}", e));
+                match retry {
+                    Some(delay) if llm_provider::sleep_unless_cancelled(delay, &running).await => {}
+                    _ => break,
+                }
+            }
+        }
+    }
+    result.latency_ms = start.elapsed().as_millis() as u64;
+    result
+}
+
+/// 在 stderr 上显示的进度条，stderr 不是终端时不显示
+struct Progress {
+    total: usize,
+    done: usize,
+    failed: usize,
+    start: Instant,
+    enabled: bool,
+}
+
+impl Progress {
+    fn new(total: usize) -> Self {
+        Self {
+            total,
+            done: 0,
+            failed: 0,
+            start: Instant::now(),
+            enabled: std::io::stderr().is_terminal(),
+        }
+    }
+
+    fn draw(&self) {
+        if !self.enabled || self.total == 0 {
+            return;
+        }
+        let filled = BAR_WIDTH * self.done / self.total;
+        let eta = match self.done {
+            0 => "-".to_string(),
+            done => {
+                let remaining = self.start.elapsed().as_secs_f64() / done as f64 * (self.total - done) as f64;
+                format!("{}s", Duration::from_secs_f64(remaining).as_secs())
+            }
+        };
+        eprint!(
+            "\r[{}{}] {}/{} failed: {} eta: {}   ",
+            "#".repeat(filled).green(),
+            "-".repeat(BAR_WIDTH - filled),
+            self.done,
+            self.total,
+            self.failed,
+            eta
+        );
+        let _ = std::io::stderr().flush();
+    }
+
+    fn finish(&self) {
+        if self.enabled {
+            eprintln!();
+        }
+    }
+}
+
+/// 批量处理输入文件中的提示词，结果逐行追加到输出文件
+/// 输出文件中已成功的 id 会被跳过，中断后重新执行同一命令即可继续
+pub async fn run(config: &Config, options: BatchOptions<'_>, running: Arc<AtomicBool>) -> Result<()> {
+    let pinned_model = options.bot.and_then(|bot| bot.model.as_deref());
+    let model_config = match options.model.as_deref().or(pinned_model) {
+        Some(name) => config
+            .get_model(name)
+            .ok_or_else(|| anyhow::anyhow!("model not found: {}", name))?,
+        None => config
+            .get_current_model()
+            .map(|(_, model_config)| model_config)
+            .ok_or_else(|| anyhow::anyhow!("no model configured"))?,
+    };
+    let mut params = model_config.params.clone();
+    if let Some(bot) = options.bot {
+        params = params.merge(&bot.params);
+    }
+    // 重试只由 --retries 控制，关闭 Provider 内部的重试，避免两层重试次数相乘
+    // 开启缓存时重新运行批处理不论 temperature 都直接使用缓存
+    let provider = config
+        .provider(model_config)?
+        .with_params(params.merge(&options.params))
+        .with_max_retries(0)
+        .with_cache(
+            config
+                .response_cache()
+                .filter(|_| config.cache_enabled())
+                .map(ResponseCache::any_temperature),
+        );
+
+    let rows = read_rows(&options.input, options.format)?;
+    let done = completed_ids(&options.output)?;
+    // 先生成所有请求，模板错误在发送任何请求之前报告
+    let mut requests = Vec::new();
+    for row in &rows {
+        if done.contains(&row.id) {
+            continue;
+        }
+        let messages = build_messages(config, &options, row).with_context(|| format!("id {}", row.id))?;
+        requests.push((row.id.clone(), messages));
+    }
+    let skipped = rows.len() - requests.len();
+    if skipped > 0 {
+        eprintln!("skipping {} prompt(s) already completed in {}", skipped, options.output.display());
+    }
+    if requests.is_empty() {
+        println!("nothing to do");
+        return Ok(());
+    }
+
+    let mut output = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(&options.output)
+        .with_context(|| format!("can not open output file {}", options.output.display()))?;
+    // 上次中断时最后一行可能没有写完
+    let existing = fs::read(&options.output).unwrap_or_default();
+    if existing.last().is_some_and(|byte| *byte != b'\n') {
+        output.write_all(b"\n")?;
+    }
+
+    let mut progress = Progress::new(requests.len());
+    progress.draw();
+    let provider = &provider;
+    let retries = options.retries;
+    let mut results = futures::stream::iter(requests)
+        .map(|(id, messages)| process(provider, id, messages, retries, running.clone()))
+        .buffer_unordered(options.concurrency.max(1));
+    while let Some(result) = results.next().await {
+        // 取消时进行中的请求会以错误结束，不写入输出，下次继续时重新请求
+        if !running.load(Ordering::SeqCst) && result.error.is_some() {
+            break;
+        }
+        progress.done += 1;
+        if result.error.is_some() {
+            progress.failed += 1;
+        }
+        let mut line = serde_json::to_string(&result)?;
+        line.push('\n');
+        output.write_all(line.as_bytes())?;
+        output.flush()?;
+        progress.draw();
+        if !running.load(Ordering::SeqCst) {
+            break;
+        }
+    }
+    progress.finish();
+
+    let succeeded = progress.done - progress.failed;
+    println!(
+        "{} succeeded, {} failed, {} skipped, results in {}",
+        succeeded.to_string().green(),
+        progress.failed.to_string().red(),
+        skipped,
+        options.output.display()
+    );
+    if !running.load(Ordering::SeqCst) {
+        return Err(anyhow::anyhow!("interrupted, run the same command again to resume"));
+    }
+    if progress.failed > 0 {
+        return Err(anyhow::anyhow!(
+            "{} prompt(s) failed, run the same command again to retry them",
+            progress.failed
+        ));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn csv_quoted_fields_keep_commas_quotes_and_newlines() {
+        let records = parse_csv("id,text\r\n1,\"a, \"\"b\"\"\"\n2,\"line one\nline two\"\n").unwrap();
+        assert_eq!(
+            records,
+            [
+                vec!["id", "text"],
+                vec!["1", "a, \"b\""],
+                vec!["2", "line one\nline two"],
+            ]
+        );
+    }
+
+    #[test]
+    fn csv_skips_bom_and_blank_lines_and_keeps_a_missing_final_newline() {
+        let records = parse_csv("\u{feff}a,b\n\n1,\n2,x").unwrap();
+        assert_eq!(records, [vec!["a", "b"], vec!["1", ""], vec!["2", "x"]]);
+    }
+
+    #[test]
+    fn csv_rejects_unterminated_quotes() {
+        assert!(parse_csv("a\n\"open").is_err());
+    }
+
+    #[test]
+    fn csv_rows_use_the_id_column_or_row_number() {
+        let rows = read_csv("prompt,id\nhi,q1\n").unwrap();
+        assert_eq!(rows[0].id, "q1");
+        let rows = read_csv("prompt\nhi\nthere\n").unwrap();
+        assert_eq!(rows.iter().map(|row| row.id.as_str()).collect::<Vec<_>>(), ["1", "2"]);
+        assert!(read_csv("a,b\n1\n").is_err());
+    }
+}
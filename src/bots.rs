@@ -1,49 +1,374 @@
 use std::fs;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::collections::HashMap;
 use serde::{Serialize, Deserialize};
-use anyhow::Result;
+use anyhow::{Context, Result};
 use colored::*;
+use crate::llm_provider::{GenerationParams, Message};
+use crate::migrate::{self, Schema};
+use crate::project::{self, ProjectConfig};
+use crate::registry::RegistryConfig;
+use crate::schema::OutputSchema;
+use crate::template::{self, TemplateContext};
 use crate::utils;
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct Bot {
     pub name: String,
     pub system_prompt: String,
+    /// 用户输入模板，例如 "translate to {{lang}}: {{input}}"
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub user_template: Option<String>,
+    /// 固定使用的模型名称，未设置时使用当前模型
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub model: Option<String>,
+    /// 输出格式
+    #[serde(default, skip_serializing_if = "OutputFormat::is_text")]
+    pub output_format: OutputFormat,
+    /// 覆盖模型默认值的生成参数
+    #[serde(flatten, default)]
+    pub params: GenerationParams,
+    /// 回复需要满足的 JSON Schema，设置后以结构化输出方式请求并在本地校验
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub output_schema: Option<serde_json::Value>,
+    /// 少样本示例对话
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub examples: Vec<BotExample>,
+}
+
+/// 机器人输出格式
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum OutputFormat {
+    #[default]
+    Text,
+    Json,
+}
+
+impl OutputFormat {
+    fn is_text(&self) -> bool {
+        *self == OutputFormat::Text
+    }
+}
+
+impl std::fmt::Display for OutputFormat {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            OutputFormat::Text => write!(f, "text"),
+            OutputFormat::Json => write!(f, "json"),
+        }
+    }
 }
 
-#[derive(Debug, Default, Serialize, Deserialize)]
+impl std::str::FromStr for OutputFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "text" => Ok(OutputFormat::Text),
+            "json" => Ok(OutputFormat::Json),
+            _ => Err(anyhow::anyhow!("unknown output format: {} (expected text or json)", s)),
+        }
+    }
+}
+
+/// 一组用户/助手示例消息
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BotExample {
+    pub user: String,
+    pub assistant: String,
+}
+
+impl Bot {
+    /// 对话开头的消息：渲染后的系统提示词以及示例对话
+    /// 系统提示词不能使用 `{{input}}`，在读取任何输入之前就报错
+    pub fn initial_messages(&self, ctx: &TemplateContext) -> Result<Vec<Message>> {
+        if template::uses_variable(&self.system_prompt, template::INPUT_VAR) {
+            return Err(anyhow::anyhow!(
+                "bot {}: system prompt: {{{{input}}}} is only available in user_template",
+                self.name
+            ));
+        }
+        let system_prompt = template::render(&self.system_prompt, ctx)
+            .map_err(|e| anyhow::anyhow!("bot {}: system prompt: {}", self.name, e))?;
+        let mut messages = vec![Message::new("system", system_prompt)];
+        for example in &self.examples {
+            messages.push(Message::new("user", example.user.clone()));
+            messages.push(Message::new("assistant", example.assistant.clone()));
+        }
+        Ok(messages)
+    }
+
+    /// 机器人设置的输出 schema，以机器人名称作为 schema 名称
+    pub fn output_schema(&self) -> Result<Option<OutputSchema>> {
+        self.output_schema
+            .as_ref()
+            .map(|schema| OutputSchema::new(&self.name, schema.clone()))
+            .transpose()
+            .with_context(|| format!("bot {}: output_schema", self.name))
+    }
+
+    /// 校验字段取值，错误信息以 prefix 开头指出具体字段，例如 `bot.example #1: user ...`
+    /// 示例从 1 开始编号，与 `gpt bots example list` 一致
+    pub fn validate(&self, prefix: &str) -> Result<()> {
+        let mut errors = Vec::new();
+        if self.name.trim().is_empty() {
+            errors.push(format!("{}name: must not be empty", prefix));
+        } else if self.name.chars().any(|c| c.is_whitespace() || c == '/') {
+            errors.push(format!("{}name: must not contain whitespace or '/'", prefix));
+        }
+        if self.system_prompt.trim().is_empty() {
+            errors.push(format!("{}system_prompt: must not be empty", prefix));
+        } else if template::uses_variable(&self.system_prompt, template::INPUT_VAR) {
+            errors.push(format!("{}system_prompt: {{{{input}}}} is only available in user_template", prefix));
+        }
+        if matches!(&self.model, Some(model) if model.trim().is_empty()) {
+            errors.push(format!("{}model: must not be empty when set", prefix));
+        }
+        let ranges = [
+            ("temperature", self.params.temperature, 0.0, 2.0),
+            ("top_p", self.params.top_p, 0.0, 1.0),
+            ("presence_penalty", self.params.presence_penalty, -2.0, 2.0),
+            ("frequency_penalty", self.params.frequency_penalty, -2.0, 2.0),
+        ];
+        for (field, value, min, max) in ranges {
+            if let Some(value) = value {
+                if !(min..=max).contains(&value) {
+                    errors.push(format!("{}{}: {} is out of range {}..={}", prefix, field, value, min, max));
+                }
+            }
+        }
+        if self.output_schema.as_ref().is_some_and(|schema| !schema.is_object()) {
+            errors.push(format!("{}output_schema: must be a table", prefix));
+        }
+        if self.params.max_tokens == Some(0) {
+            errors.push(format!("{}max_tokens: must be greater than 0", prefix));
+        }
+        for (i, example) in self.examples.iter().enumerate() {
+            if example.user.trim().is_empty() {
+                errors.push(format!("{}example #{}: user must not be empty", prefix, i + 1));
+            }
+            if example.assistant.trim().is_empty() {
+                errors.push(format!("{}example #{}: assistant must not be empty", prefix, i + 1));
+            }
+        }
+
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(anyhow::anyhow!(errors.join("\n")))
+        }
+    }
+
+    /// 按用户输入模板渲染输入，没有模板时原样返回
+    pub fn render_input(&self, input: &str, ctx: &TemplateContext) -> Result<String> {
+        match &self.user_template {
+            Some(user_template) => {
+                let ctx = ctx.clone().with_input(input);
+                template::render(user_template, &ctx)
+                    .map_err(|e| anyhow::anyhow!("bot {}: user template: {}", self.name, e))
+            }
+            None => Ok(input.to_string()),
+        }
+    }
+}
+
+/// 命令行中已被占用的参数，别名不能与之冲突
+#[derive(Debug, Default)]
+pub struct ReservedFlags {
+    pub shorts: Vec<char>,
+    pub longs: Vec<String>,
+}
+
+/// 检查别名能否作为命令行参数使用
+/// 单字符别名注册为 `-<alias>`，多字符别名注册为 `--<alias>`
+pub fn check_alias(alias: &str, reserved: &ReservedFlags) -> Result<()> {
+    if alias.is_empty() {
+        return Err(anyhow::anyhow!("alias must not be empty"));
+    }
+    if alias.starts_with('-')
+        || !alias.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+    {
+        return Err(anyhow::anyhow!(
+            "alias '{}' may only contain letters, digits, '-' and '_' and must not start with '-'",
+            alias
+        ));
+    }
+    let mut chars = alias.chars();
+    match (chars.next(), chars.next()) {
+        (Some(c), None) if reserved.shorts.contains(&c) => Err(anyhow::anyhow!(
+            "alias '{}' conflicts with the built-in flag -{}",
+            alias,
+            c
+        )),
+        (Some(_), Some(_)) if reserved.longs.iter().any(|long| long == alias) => Err(
+            anyhow::anyhow!("alias '{}' conflicts with the built-in flag --{}", alias, alias),
+        ),
+        _ => Ok(()),
+    }
+}
+
+/// 当前支持的机器人包格式版本
+const BUNDLE_VERSION: u32 = 1;
+
+/// 可移植的机器人包，用于导入导出
+#[derive(Debug, Serialize, Deserialize)]
+pub struct BotBundle {
+    pub version: u32,
+    /// 指向该机器人的别名
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub aliases: Vec<String>,
+    pub bot: Bot,
+}
+
+impl BotBundle {
+    fn load(path: &Path) -> Result<Self> {
+        let content = fs::read_to_string(path)
+            .with_context(|| format!("failed to read {}", path.display()))?;
+        let bundle: BotBundle = toml::from_str(&content)
+            .with_context(|| format!("invalid bot bundle {}", path.display()))?;
+        if bundle.version > BUNDLE_VERSION {
+            return Err(anyhow::anyhow!(
+                "{}: version: bundle version {} is newer than supported version {}, please update gpt-shell",
+                path.display(),
+                bundle.version,
+                BUNDLE_VERSION
+            ));
+        }
+        let mut errors = Vec::new();
+        if let Err(e) = bundle.bot.validate("bot.") {
+            errors.push(e.to_string());
+        }
+        for (i, alias) in bundle.aliases.iter().enumerate() {
+            if let Err(e) = check_alias(alias, &ReservedFlags::default()) {
+                errors.push(format!("alias #{}: {}", i + 1, e));
+            }
+        }
+        if !errors.is_empty() {
+            return Err(anyhow::anyhow!("{}:\n{}", path.display(), errors.join("\n")));
+        }
+        Ok(bundle)
+    }
+}
+
+/// 导入时与已有机器人或别名重名的处理方式
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ConflictStrategy {
+    Skip,
+    Overwrite,
+    Rename,
+}
+
+impl std::str::FromStr for ConflictStrategy {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "skip" => Ok(ConflictStrategy::Skip),
+            "overwrite" => Ok(ConflictStrategy::Overwrite),
+            "rename" => Ok(ConflictStrategy::Rename),
+            _ => Err(anyhow::anyhow!("unknown conflict strategy: {} (expected skip, overwrite or rename)", s)),
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
 pub struct BotsConfig {
+    /// 配置文件格式版本
+    #[serde(default)]
+    pub version: u32,
     pub bots: HashMap<String, Bot>,
     #[serde(default)]
     pub aliases: HashMap<String, String>,
     #[serde(default)]
     pub current: Option<String>,
+    /// 项目级机器人与别名，读取时覆盖用户配置，不会被保存
+    #[serde(skip)]
+    pub project: Option<ProjectConfig>,
+    /// registry 中的机器人，名称带命名空间，例如 team/reviewer
+    #[serde(skip)]
+    pub registry: HashMap<String, Bot>,
+}
+
+impl Default for BotsConfig {
+    fn default() -> Self {
+        Self {
+            version: migrate::BOTS_VERSION,
+            bots: HashMap::new(),
+            aliases: HashMap::new(),
+            current: None,
+            project: None,
+            registry: HashMap::new(),
+        }
+    }
 }
 
 impl BotsConfig {
     pub fn load() -> Result<Self> {
-        if let Some(path) = Self::get_path() {
-            if path.exists() {
-                let content = fs::read_to_string(&path)?;
-                Ok(toml::from_str(&content)?)
-            } else {
-                let config = BotsConfig::default();
-                config.save()?;
-                Ok(config)
-            }
+        let mut config = if let Some(path) = Self::get_path() {
+            let _lock = utils::lock_file(&path)?;
+            Self::read(&path)?
+        } else {
+            BotsConfig::default()
+        };
+        config.project = ProjectConfig::discover().map(|mut project| {
+            let (mut bots, mut aliases) = (std::mem::take(&mut project.bots), std::mem::take(&mut project.aliases));
+            project::drop_shadowed(&project, "bot", &mut bots, |name| config.bots.contains_key(name));
+            project::drop_shadowed(&project, "alias", &mut aliases, |name| config.aliases.contains_key(name));
+            project.bots = bots;
+            project.aliases = aliases;
+            project
+        });
+        config.registry = RegistryConfig::available_bots();
+        Ok(config)
+    }
+
+    /// 只解析指定的 bots.toml，不合并项目与 registry 配置
+    /// 旧版本文件仅在内存中升级
+    pub fn load_from(path: &Path) -> Result<Self> {
+        Self::parse(path, false)
+    }
+
+    /// 读取用户的 bots.toml，不存在时创建，调用方需持有文件锁
+    fn read(path: &Path) -> Result<Self> {
+        if path.exists() {
+            Self::parse(path, true)
         } else {
-            Ok(BotsConfig::default())
+            let config = BotsConfig::default();
+            config.save()?;
+            Ok(config)
         }
     }
+
+    fn parse(path: &Path, persist: bool) -> Result<Self> {
+        let content = fs::read_to_string(path)?;
+        let content = migrate::upgrade(Schema::Bots, path, &content, persist)?;
+        toml::from_str(&content).with_context(|| format!("invalid bots config: {}", path.display()))
+    }
     
-    pub fn save(&self) -> Result<()> {
+    /// 保存到用户的 bots.toml，调用方需持有文件锁，修改配置请使用 update
+    fn save(&self) -> Result<()> {
         if let Some(path) = Self::get_path() {
             let content = toml::to_string_pretty(self)?;
             utils::save_file(&content, &path)?;
         }
         Ok(())
     }
+
+    /// 在文件锁内重新读取 bots.toml、修改并保存，避免并发修改互相覆盖
+    fn update<T>(&mut self, f: impl FnOnce(&mut Self) -> Result<T>) -> Result<T> {
+        let Some(path) = Self::get_path() else {
+            return f(self);
+        };
+        let _lock = utils::lock_file(&path)?;
+        let mut config = Self::read(&path)?;
+        config.project = self.project.clone();
+        config.registry = self.registry.clone();
+        let result = f(&mut config)?;
+        config.save()?;
+        *self = config;
+        Ok(result)
+    }
     
     pub fn get_path() -> Option<PathBuf> {
         let mut path = utils::get_config_dir()?;
@@ -58,71 +383,326 @@ impl BotsConfig {
         Ok(())
     }
     
-    pub fn add_bot(&mut self, name: String, system_prompt: String) -> Result<()> {
-        let bot = Bot {
-            name: name.clone(),
-            system_prompt,
-        };
-        self.bots.insert(name.clone(), bot);
-        self.save()?;
+    pub fn add_bot(&mut self, bot: Bot) -> Result<()> {
+        let name = bot.name.clone();
+        self.update(|config| {
+            config.bots.insert(name.clone(), bot);
+            Ok(())
+        })?;
         println!("bot added: {}", name.green());
         Ok(())
     }
     
     pub fn remove_bot(&mut self, name: &str) -> Result<()> {
-        if self.bots.remove(name).is_some() {
-            self.save()?;
-            println!("bot removed: {}", name.green());
-            Ok(())
-        } else {
-            Err(anyhow::anyhow!("bot not found: {}", name))
-        }
+        self.update(|config| match config.bots.remove(name) {
+            Some(_) => Ok(()),
+            None => Err(anyhow::anyhow!("bot not found: {}", name)),
+        })?;
+        println!("bot removed: {}", name.green());
+        Ok(())
     }
     
+    /// 按名称查找机器人，依次查找用户配置、项目配置和 registry
     pub fn get_bot(&self, name: &str) -> Option<&Bot> {
-        self.bots.get(name)
+        self.bots
+            .get(name)
+            .or_else(|| self.project.as_ref().and_then(|p| p.bots.get(name)))
+            .or_else(|| self.registry.get(name))
+    }
+
+    /// 所有可用的机器人名称，包括项目配置与 registry 中的机器人，按名称排序
+    pub fn bot_names(&self) -> Vec<String> {
+        let mut names: Vec<String> = self
+            .bots
+            .keys()
+            .chain(self.project.iter().flat_map(|p| p.bots.keys()))
+            .chain(self.registry.keys())
+            .cloned()
+            .collect();
+        names.sort();
+        names.dedup();
+        names
     }
 
     pub fn list_bots(&self) {
-        if self.bots.is_empty() {
+        let project_bots = self.project.as_ref().map(|p| &p.bots);
+        if self.bots.is_empty()
+            && project_bots.is_none_or(|b| b.is_empty())
+            && self.registry.is_empty()
+        {
             println!("no bots added yet");
             return;
         }
 
         println!("available bots:");
-        for (name, bot) in &self.bots {
+        let user_bots = self
+            .bots
+            .iter()
+            .filter(|(name, _)| project_bots.is_none_or(|b| !b.contains_key(*name)))
+            .map(|(name, bot)| (name, bot, ""));
+        let registry_bots = self
+            .registry
+            .iter()
+            .filter(|(name, _)| {
+                !self.bots.contains_key(*name) && project_bots.is_none_or(|b| !b.contains_key(*name))
+            })
+            .map(|(name, bot)| (name, bot, " (registry)"));
+        let project_bots = project_bots
+            .into_iter()
+            .flatten()
+            .map(|(name, bot)| (name, bot, " (project)"));
+        for (name, bot, source) in user_bots.chain(project_bots).chain(registry_bots) {
             let current_marker = if Some(name) == self.current.as_ref() {
                 "* ".bright_green()
             } else {
                 "  ".into()
             };
-            println!("{}{} (system prompt: {})", current_marker, name.green(), bot.system_prompt);
+            let source = source.cyan();
+            println!("{}{}{} (system prompt: {})", current_marker, name.green(), source, bot.system_prompt);
+            if let Some(model) = &bot.model {
+                println!("    model: {}", model);
+            }
+            if bot.output_format != OutputFormat::Text {
+                println!("    output format: {}", bot.output_format);
+            }
+            if bot.output_schema.is_some() {
+                println!("    output schema: yes");
+            }
+            if !bot.params.is_empty() {
+                println!("    params: {}", bot.params);
+            }
+            if !bot.examples.is_empty() {
+                println!("    examples: {}", bot.examples.len());
+            }
         }
     }
 
-    pub fn set_alias(&mut self, bot: String, alias: String) -> Result<()> {
-        if alias.len() != 1 {
-            return Err(anyhow::anyhow!("alias must be a single character"));
+    /// 模型重命名后更新固定了该模型的用户级机器人
+    pub fn rename_model(&mut self, name: &str, new_name: &str) -> Result<()> {
+        if !self.bots.values().any(|bot| bot.model.as_deref() == Some(name)) {
+            return Ok(());
         }
-        // 检查器人是否存在
-        if !self.bots.contains_key(&bot) {
-            return Err(anyhow::anyhow!("bot not found: {}", bot));
+        let updated = self.update(|config| {
+            let mut updated = Vec::new();
+            for bot in config.bots.values_mut() {
+                if bot.model.as_deref() == Some(name) {
+                    bot.model = Some(new_name.to_string());
+                    updated.push(bot.name.clone());
+                }
+            }
+            Ok(updated)
+        })?;
+        for bot in updated {
+            println!("  bot {} now uses model {}", bot.green(), new_name);
         }
-        // 添加或更新别名
-        self.aliases.insert(alias.clone(), bot.clone());
-        self.save()?;
-        println!("alias set: {} -> {}", alias.green(), bot.green());
         Ok(())
     }
 
-    pub fn remove_alias(&mut self, alias: &str) -> Result<()> {
-        if let Some(bot_name) = self.aliases.remove(alias) {
-            self.save()?;
-            println!("alias removed: {} -> {}", alias.green(), bot_name.green());
+    pub fn add_example(&mut self, bot: &str, user: String, assistant: String) -> Result<()> {
+        let index = self.update(|config| {
+            let entry = config.user_bot_mut(bot)?;
+            entry.examples.push(BotExample { user, assistant });
+            Ok(entry.examples.len())
+        })?;
+        println!("example #{} added to bot: {}", index, bot.green());
+        Ok(())
+    }
+
+    /// 删除示例，index 从 1 开始，与 list_examples 的编号一致
+    pub fn remove_example(&mut self, bot: &str, index: usize) -> Result<()> {
+        self.update(|config| {
+            let entry = config.user_bot_mut(bot)?;
+            if index == 0 || index > entry.examples.len() {
+                return Err(anyhow::anyhow!(
+                    "example #{} not found, bot {} has {} examples",
+                    index,
+                    bot,
+                    entry.examples.len()
+                ));
+            }
+            entry.examples.remove(index - 1);
             Ok(())
+        })?;
+        println!("example #{} removed from bot: {}", index, bot.green());
+        Ok(())
+    }
+
+    pub fn list_examples(&self, bot: &str) -> Result<()> {
+        let entry = self
+            .get_bot(bot)
+            .ok_or_else(|| anyhow::anyhow!("bot not found: {}", bot))?;
+        if entry.examples.is_empty() {
+            println!("no examples for bot {} yet", bot.green());
+            return Ok(());
+        }
+
+        println!("examples of {}:", bot.green());
+        for (i, example) in entry.examples.iter().enumerate() {
+            println!("#{}", i + 1);
+            println!("  {} {}", "user:".cyan(), example.user);
+            println!("  {} {}", "assistant:".cyan(), example.assistant);
+        }
+        Ok(())
+    }
+
+    /// 获取可修改的用户级机器人，项目级机器人只能在项目配置中修改
+    fn user_bot_mut(&mut self, name: &str) -> Result<&mut Bot> {
+        if self.project.as_ref().is_some_and(|p| p.bots.contains_key(name)) {
+            return Err(anyhow::anyhow!(
+                "bot {} is defined in project config, edit it there instead",
+                name
+            ));
+        }
+        self.bots
+            .get_mut(name)
+            .ok_or_else(|| anyhow::anyhow!("bot not found: {}", name))
+    }
+
+    /// 导出机器人及其别名，未指定输出文件时打印到标准输出
+    pub fn export_bot(&self, name: &str, output: Option<&PathBuf>) -> Result<()> {
+        let bot = self
+            .get_bot(name)
+            .ok_or_else(|| anyhow::anyhow!("bot not found: {}", name))?;
+        let mut aliases: Vec<String> = self
+            .all_aliases()
+            .into_iter()
+            .filter(|(_, target)| target == name)
+            .map(|(alias, _)| alias)
+            .collect();
+        aliases.sort();
+        let bundle = BotBundle {
+            version: BUNDLE_VERSION,
+            aliases,
+            bot: bot.clone(),
+        };
+        let content = toml::to_string_pretty(&bundle)?;
+        match output {
+            Some(path) => {
+                utils::save_file(&content, path)?;
+                eprintln!("bot exported: {} -> {}", name.green(), path.display());
+            }
+            None => print!("{}", content),
+        }
+        Ok(())
+    }
+
+    /// 从文件或目录（其中所有 .toml 文件）导入机器人包
+    /// 所有文件校验通过后才会写入配置
+    pub fn import_bots(&mut self, path: &Path, strategy: ConflictStrategy, reserved: &ReservedFlags) -> Result<()> {
+        let files = if path.is_dir() {
+            let mut files = Vec::new();
+            for entry in fs::read_dir(path)? {
+                let entry_path = entry?.path();
+                if entry_path.extension().is_some_and(|ext| ext == "toml") {
+                    files.push(entry_path);
+                }
+            }
+            files.sort();
+            files
         } else {
-            Err(anyhow::anyhow!("alias not found: {}", alias))
+            vec![path.to_path_buf()]
+        };
+        if files.is_empty() {
+            return Err(anyhow::anyhow!("no bot bundles found in {}", path.display()));
         }
+
+        let bundles = files
+            .iter()
+            .map(|file| BotBundle::load(file))
+            .collect::<Result<Vec<_>>>()?;
+
+        let imported = self.update(|config| {
+            let mut imported = 0;
+            for bundle in bundles {
+                let mut bot = bundle.bot;
+                let original = bot.name.clone();
+                if config.bots.contains_key(&bot.name) {
+                    match strategy {
+                        ConflictStrategy::Skip => {
+                            println!("skipped existing bot: {}", original.yellow());
+                            continue;
+                        }
+                        ConflictStrategy::Overwrite => {}
+                        ConflictStrategy::Rename => {
+                            let mut i = 2;
+                            while config.bots.contains_key(&format!("{}-{}", original, i)) {
+                                i += 1;
+                            }
+                            bot.name = format!("{}-{}", original, i);
+                        }
+                    }
+                }
+
+                let name = bot.name.clone();
+                config.bots.insert(name.clone(), bot);
+                if name == original {
+                    println!("bot imported: {}", name.green());
+                } else {
+                    println!("bot imported: {} (renamed from {})", name.green(), original);
+                }
+                imported += 1;
+
+                for alias in bundle.aliases {
+                    if let Err(e) = check_alias(&alias, reserved) {
+                        println!("  skipped alias {}: {}", alias.yellow(), e);
+                        continue;
+                    }
+                    match config.aliases.get(&alias) {
+                        Some(target) if *target != name && strategy != ConflictStrategy::Overwrite => {
+                            println!(
+                                "  skipped alias {}: already used by {}",
+                                alias.yellow(),
+                                target
+                            );
+                        }
+                        _ => {
+                            println!("  alias set: {} -> {}", alias.green(), name.green());
+                            config.aliases.insert(alias, name.clone());
+                        }
+                    }
+                }
+            }
+            Ok(imported)
+        })?;
+        println!("{} bot(s) imported", imported);
+        Ok(())
+    }
+
+    pub fn set_alias(&mut self, bot: String, alias: String, reserved: &ReservedFlags) -> Result<()> {
+        check_alias(&alias, reserved)?;
+        self.update(|config| {
+            // 检查机器人是否存在
+            if config.get_bot(&bot).is_none() {
+                return Err(anyhow::anyhow!("bot not found: {}", bot));
+            }
+            // 检查别名是否已被其他机器人占用
+            if let Some(existing) = config.get_bot_by_alias(&alias) {
+                if *existing != bot {
+                    return Err(anyhow::anyhow!(
+                        "alias '{}' is already used by bot {}, remove it first with `gpt bots alias remove {}`",
+                        alias,
+                        existing,
+                        alias
+                    ));
+                }
+            }
+            // 添加或更新别名
+            config.aliases.insert(alias.clone(), bot.clone());
+            Ok(())
+        })?;
+        println!("alias set: {} -> {}", alias.green(), bot.green());
+        Ok(())
+    }
+
+    pub fn remove_alias(&mut self, alias: &str) -> Result<()> {
+        let bot_name = self.update(|config| {
+            config
+                .aliases
+                .remove(alias)
+                .ok_or_else(|| anyhow::anyhow!("alias not found: {}", alias))
+        })?;
+        println!("alias removed: {} -> {}", alias.green(), bot_name.green());
+        Ok(())
     }
 
     pub fn list_aliases(&self) {
@@ -138,27 +718,40 @@ impl BotsConfig {
     }
 
     pub fn get_bot_by_alias(&self, alias: &str) -> Option<&String> {
-        self.aliases.get(alias)
+        self.aliases
+            .get(alias)
+            .or_else(|| self.project.as_ref().and_then(|p| p.aliases.get(alias)))
+    }
+
+    /// 用户级与项目级别名的合并结果，同名时使用用户级别名
+    pub fn all_aliases(&self) -> HashMap<String, String> {
+        let mut aliases = self.project.as_ref().map(|p| p.aliases.clone()).unwrap_or_default();
+        aliases.extend(self.aliases.clone());
+        aliases
     }
 
     pub fn set_current(&mut self, name: &str) -> Result<()> {
-        if !self.bots.contains_key(name) {
-            return Err(anyhow::anyhow!("机器人不存在: {}", name));
-        }
-        self.current = Some(name.to_string());
-        self.save()?;
+        self.update(|config| {
+            if config.get_bot(name).is_none() {
+                return Err(anyhow::anyhow!("机器人不存在: {}", name));
+            }
+            config.current = Some(name.to_string());
+            Ok(())
+        })?;
         println!("当前机器人已设置为: {}", name.green());
         Ok(())
     }
 
     pub fn clear_current(&mut self) -> Result<()> {
-        self.current = None;
-        self.save()?;
+        self.update(|config| {
+            config.current = None;
+            Ok(())
+        })?;
         println!("已清除当前机器人设置");
         Ok(())
     }
 
     pub fn get_current(&self) -> Option<&Bot> {
-        self.current.as_ref().and_then(|name| self.bots.get(name))
+        self.current.as_ref().and_then(|name| self.get_bot(name))
     }
 } 
\ No newline at end of file
@@ -0,0 +1,343 @@
+use crate::llm_provider::{ChatEvent, ChatStream, FinishReason, ToolCallDelta, Usage};
+use crate::utils;
+use anyhow::Result;
+use colored::*;
+use futures::StreamExt;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// 本进程是否已经清理过缓存目录
+static PRUNED: AtomicBool = AtomicBool::new(false);
+
+/// 磁盘上的响应缓存
+/// 以接口地址和请求体（不含 stream）的哈希为键，每条缓存是缓存目录下的一个 JSON 文件
+/// 默认只缓存 temperature 为 0 的请求，其他请求每次的回复本就应该不同
+#[derive(Debug, Clone)]
+pub struct ResponseCache {
+    dir: PathBuf,
+    ttl: Duration,
+    max_bytes: u64,
+    any_temperature: bool,
+}
+
+/// 缓存的一次完整回复，命中时按事件重新播放
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct CachedResponse {
+    /// 写入时间（Unix 秒）
+    created: u64,
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    reasoning: String,
+    #[serde(default)]
+    content: String,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    tool_calls: Vec<CachedToolCall>,
+    #[serde(default)]
+    finish_reason: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    usage: Option<Usage>,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct CachedToolCall {
+    #[serde(default)]
+    id: Option<String>,
+    #[serde(default)]
+    name: Option<String>,
+    #[serde(default)]
+    arguments: String,
+}
+
+impl CachedResponse {
+    fn record(&mut self, event: &ChatEvent) {
+        match event {
+            ChatEvent::TextDelta(text) => self.content.push_str(text),
+            ChatEvent::ReasoningDelta(text) => self.reasoning.push_str(text),
+            ChatEvent::ToolCallDelta(delta) => {
+                if self.tool_calls.len() <= delta.index {
+                    self.tool_calls.resize_with(delta.index + 1, CachedToolCall::default);
+                }
+                let call = &mut self.tool_calls[delta.index];
+                if delta.id.is_some() {
+                    call.id = delta.id.clone();
+                }
+                if delta.name.is_some() {
+                    call.name = delta.name.clone();
+                }
+                call.arguments.push_str(&delta.arguments);
+            }
+            ChatEvent::Usage(usage) => self.usage = Some(usage.clone()),
+            ChatEvent::Finish(reason) => self.finish_reason = Some(reason.to_string()),
+            ChatEvent::Error(_) => {}
+        }
+    }
+
+    fn into_events(self) -> Vec<ChatEvent> {
+        let mut events = Vec::new();
+        if !self.reasoning.is_empty() {
+            events.push(ChatEvent::ReasoningDelta(self.reasoning));
+        }
+        if !self.content.is_empty() {
+            events.push(ChatEvent::TextDelta(self.content));
+        }
+        for (index, call) in self.tool_calls.into_iter().enumerate() {
+            events.push(ChatEvent::ToolCallDelta(ToolCallDelta {
+                index,
+                id: call.id,
+                name: call.name,
+                arguments: call.arguments,
+            }));
+        }
+        if let Some(reason) = self.finish_reason.and_then(|reason| FinishReason::parse(&reason.into())) {
+            events.push(ChatEvent::Finish(reason));
+        }
+        if let Some(usage) = self.usage {
+            events.push(ChatEvent::Usage(usage));
+        }
+        events
+    }
+}
+
+/// 缓存目录的统计信息
+struct CacheStats {
+    entries: usize,
+    expired: usize,
+    bytes: u64,
+    oldest: Option<SystemTime>,
+    newest: Option<SystemTime>,
+}
+
+/// 缓存条目的文件与修改时间
+struct Entry {
+    path: PathBuf,
+    modified: SystemTime,
+    size: u64,
+}
+
+impl ResponseCache {
+    pub fn new(dir: PathBuf, ttl: Duration, max_bytes: u64) -> Self {
+        Self {
+            dir,
+            ttl,
+            max_bytes,
+            any_temperature: false,
+        }
+    }
+
+    /// 不论 temperature 都使用缓存，用于重复运行的批处理
+    pub fn any_temperature(mut self) -> Self {
+        self.any_temperature = true;
+        self
+    }
+
+    /// 该 temperature 的请求是否使用缓存
+    pub fn applies_to(&self, temperature: Option<f64>) -> bool {
+        self.any_temperature || temperature == Some(0.0)
+    }
+
+    /// 默认的缓存目录：配置目录下的 cache/
+    pub fn default_dir() -> Option<PathBuf> {
+        Some(utils::get_config_dir()?.join("cache"))
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// 计算请求的缓存键，流式与非流式请求共用同一条缓存
+    pub fn key(api_url: &str, request_body: &serde_json::Value) -> String {
+        let mut body = request_body.clone();
+        if let Some(body) = body.as_object_mut() {
+            body.remove("stream");
+            body.remove("stream_options");
+        }
+        let mut hasher = Sha256::new();
+        hasher.update(api_url.as_bytes());
+        hasher.update(b"\n");
+        hasher.update(body.to_string().as_bytes());
+        hasher
+            .finalize()
+            .iter()
+            .map(|byte| format!("{:02x}", byte))
+            .collect()
+    }
+
+    fn path(&self, key: &str) -> PathBuf {
+        self.dir.join(format!("{}.json", key))
+    }
+
+    /// 读取未过期的缓存，以事件流返回，过期或损坏的条目会被删除
+    pub fn get(&self, key: &str) -> Option<ChatStream> {
+        let path = self.path(key);
+        let content = fs::read_to_string(&path).ok()?;
+        let cached = match serde_json::from_str::<CachedResponse>(&content) {
+            Ok(cached) if now().saturating_sub(cached.created) < self.ttl.as_secs() => cached,
+            _ => {
+                let _ = fs::remove_file(&path);
+                return None;
+            }
+        };
+        Some(Box::pin(futures::stream::iter(cached.into_events())))
+    }
+
+    /// 包装回复流，正常结束时写入缓存
+    /// 出错、被中断或没有结束原因的回复不会被缓存
+    pub fn record(&self, key: String, events: ChatStream) -> ChatStream {
+        let cache = self.clone();
+        let stream = futures::stream::unfold(
+            (events, CachedResponse::default(), Some(key)),
+            move |(mut events, mut recorded, mut key)| {
+                let cache = cache.clone();
+                async move {
+                    match events.next().await {
+                        Some(event) => {
+                            if matches!(event, ChatEvent::Error(_)) {
+                                key = None;
+                            }
+                            recorded.record(&event);
+                            Some((event, (events, recorded, key)))
+                        }
+                        None => {
+                            if let Some(key) = key.filter(|_| recorded.finish_reason.is_some()) {
+                                recorded.created = now();
+                                if let Err(e) = cache.put(&key, &recorded) {
+                                    eprintln!("warning: failed to write response cache: {}", e);
+                                }
+                            }
+                            None
+                        }
+                    }
+                }
+            },
+        );
+        Box::pin(stream)
+    }
+
+    fn put(&self, key: &str, response: &CachedResponse) -> Result<()> {
+        let content = serde_json::to_string(response)?;
+        utils::save_file(&content, &self.path(key))?;
+        // 每个进程只在第一次写入时清理一次，避免批处理时每次写入都遍历整个目录
+        if PRUNED.swap(true, Ordering::SeqCst) {
+            return Ok(());
+        }
+        self.prune()
+    }
+
+    /// 删除过期条目，总大小超过上限时从最旧的条目开始删除
+    fn prune(&self) -> Result<()> {
+        let mut entries = entries(&self.dir)?;
+        let expires = self.expires();
+        entries.retain(|entry| entry.modified >= expires || fs::remove_file(&entry.path).is_err());
+        entries.sort_by_key(|entry| entry.modified);
+        let mut total: u64 = entries.iter().map(|entry| entry.size).sum();
+        for entry in entries {
+            if total <= self.max_bytes {
+                break;
+            }
+            if fs::remove_file(&entry.path).is_ok() {
+                total = total.saturating_sub(entry.size);
+            }
+        }
+        Ok(())
+    }
+
+    /// 早于该时间写入的条目已过期
+    fn expires(&self) -> SystemTime {
+        SystemTime::now().checked_sub(self.ttl).unwrap_or(UNIX_EPOCH)
+    }
+
+    /// 输出缓存目录的统计信息
+    pub fn print_stats(&self, enabled: bool) -> Result<()> {
+        let stats = self.stats()?;
+        let age = |time: Option<SystemTime>| match time.and_then(|time| time.elapsed().ok()) {
+            Some(elapsed) => format!("{} ago", format_span(elapsed.as_secs())),
+            None => "-".to_string(),
+        };
+        println!(
+            "response cache: {} (ttl {}, max {})",
+            if enabled { "enabled".green() } else { "disabled".yellow() },
+            format_span(self.ttl.as_secs()),
+            format_size(self.max_bytes)
+        );
+        println!("directory: {}", self.dir.display());
+        println!("entries:   {} ({} expired)", stats.entries, stats.expired);
+        println!("size:      {}", format_size(stats.bytes));
+        println!("oldest:    {}", age(stats.oldest));
+        println!("newest:    {}", age(stats.newest));
+        Ok(())
+    }
+
+    fn stats(&self) -> Result<CacheStats> {
+        let entries = entries(&self.dir)?;
+        let expires = self.expires();
+        Ok(CacheStats {
+            entries: entries.len(),
+            expired: entries.iter().filter(|entry| entry.modified < expires).count(),
+            bytes: entries.iter().map(|entry| entry.size).sum(),
+            oldest: entries.iter().map(|entry| entry.modified).min(),
+            newest: entries.iter().map(|entry| entry.modified).max(),
+        })
+    }
+
+    /// 删除所有缓存条目，返回删除的数量
+    pub fn clear(&self) -> Result<usize> {
+        let entries = entries(&self.dir)?;
+        for entry in &entries {
+            fs::remove_file(&entry.path)?;
+        }
+        Ok(entries.len())
+    }
+}
+
+/// 列出缓存目录中的条目，目录不存在时返回空列表
+fn entries(dir: &Path) -> Result<Vec<Entry>> {
+    let read_dir = match fs::read_dir(dir) {
+        Ok(read_dir) => read_dir,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e.into()),
+    };
+    let mut entries = Vec::new();
+    for item in read_dir {
+        let path = item?.path();
+        if path.extension().and_then(|ext| ext.to_str()) != Some("json") {
+            continue;
+        }
+        let Ok(metadata) = fs::metadata(&path) else {
+            continue;
+        };
+        entries.push(Entry {
+            modified: metadata.modified().unwrap_or(UNIX_EPOCH),
+            size: metadata.len(),
+            path,
+        });
+    }
+    Ok(entries)
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs())
+        .unwrap_or_default()
+}
+
+/// 以最大的单位显示时长，例如 90000 -> 1d
+fn format_span(seconds: u64) -> String {
+    match seconds {
+        0..=59 => format!("{}s", seconds),
+        60..=3599 => format!("{}m", seconds / 60),
+        3600..=86399 => format!("{}h", seconds / 3600),
+        _ => format!("{}d", seconds / 86400),
+    }
+}
+
+fn format_size(bytes: u64) -> String {
+    const MB: f64 = 1024.0 * 1024.0;
+    if bytes < 1024 * 1024 {
+        return format!("{:.1} KB", bytes as f64 / 1024.0);
+    }
+    format!("{:.1} MB", bytes as f64 / MB)
+}
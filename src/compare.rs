@@ -0,0 +1,498 @@
+use crate::config::Config;
+use crate::llm_provider::{self, ChatEvent, FinishReason, GenerationParams, LLMProvider, Message, Provider, Usage};
+use crate::schema::{self, OutputSchema};
+use crate::utils;
+use anyhow::Result;
+use colored::*;
+use futures::stream::FuturesUnordered;
+use futures::StreamExt;
+use std::io::{self, Write};
+use std::sync::atomic::AtomicBool;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::mpsc;
+use unicode_width::{UnicodeWidthChar, UnicodeWidthStr};
+
+/// 无法获取终端宽度时使用的默认宽度
+const DEFAULT_WIDTH: usize = 80;
+
+/// 并排显示时列之间的分隔符
+const COLUMN_SEPARATOR: &str = " │ ";
+
+const JUDGE_PROMPT: &str = "You are an impartial judge. Score every answer to the question from 1 to 10 \
+for correctness, completeness and clarity. Reply with JSON only, in the form \
+{\"scores\": [{\"answer\": \"A\", \"score\": 8, \"reason\": \"one short sentence\"}]}.";
+
+/// 比较结果的展示方式
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Layout {
+    /// 按模型顺序依次输出，当前模型的回复边生成边显示
+    Sequential,
+    /// 全部完成后并排输出
+    Columns,
+}
+
+struct Answer {
+    name: String,
+    text: String,
+    latency: Duration,
+    ttft: Option<Duration>,
+    usage: Option<Usage>,
+    finish: Option<FinishReason>,
+    error: Option<String>,
+    score: Option<Score>,
+}
+
+struct Score {
+    score: f64,
+    reason: String,
+}
+
+impl Answer {
+    fn title(&self) -> String {
+        match &self.error {
+            Some(_) => format!("{} (failed)", self.name),
+            None => format!("{} ({}ms)", self.name, self.latency.as_millis()),
+        }
+    }
+
+    fn body(&self) -> &str {
+        self.error.as_deref().unwrap_or(&self.text)
+    }
+}
+
+/// 回复片段，附带模型的序号
+type Progress = mpsc::UnboundedSender<(usize, String)>;
+
+/// 通过一个模型获取完整回复，失败时记录错误而不是中断比较
+/// progress: 收到的回复片段同时发送到这里，用于边生成边显示
+async fn ask(name: String, provider: Result<Provider>, messages: Vec<Message>, stream: bool, running: Arc<AtomicBool>, progress: Option<(usize, Progress)>) -> Answer {
+    let start = Instant::now();
+    let mut answer = Answer {
+        name,
+        text: String::new(),
+        latency: Duration::ZERO,
+        ttft: None,
+        usage: None,
+        finish: None,
+        error: None,
+        score: None,
+    };
+    let result: Result<()> = async {
+        let mut events = provider?.chat(messages, stream, running).await?;
+        while let Some(event) = events.next().await {
+            match event {
+                ChatEvent::TextDelta(text) => {
+                    answer.ttft.get_or_insert_with(|| start.elapsed());
+                    if let Some((i, progress)) = &progress {
+                        let _ = progress.send((*i, text.clone()));
+                    }
+                    answer.text.push_str(&text);
+                }
+                ChatEvent::ReasoningDelta(_) => {
+                    answer.ttft.get_or_insert_with(|| start.elapsed());
+                }
+                ChatEvent::Usage(usage) => answer.usage = Some(usage),
+                ChatEvent::Finish(reason) => answer.finish = Some(reason),
+                ChatEvent::Error(e) => return Err(e.into()),
+                ChatEvent::ToolCallDelta(_) => {}
+            }
+        }
+        Ok(())
+    }
+    .await;
+    answer.latency = start.elapsed();
+    if let Err(e) = result {
+        answer.error = Some(format!("{:#}", e));
+    }
+    answer
+}
+
+/// 当前模型的片段直接输出，其他模型的片段缓存起来
+fn route(buffers: &mut [String], current: usize, i: usize, text: String) -> io::Result<()> {
+    if i == current {
+        print!("{}", text);
+        io::stdout().flush()
+    } else {
+        buffers[i].push_str(&text);
+        Ok(())
+    }
+}
+
+/// 按模型顺序输出回复：当前模型的片段直接输出，其他模型的片段先缓存，轮到时再输出
+/// 耗时与 token 用量在最后的汇总表中显示
+async fn print_sequential<F>(names: &[String], mut pending: FuturesUnordered<F>, mut progress: mpsc::UnboundedReceiver<(usize, String)>) -> Result<Vec<Answer>>
+where
+    F: std::future::Future<Output = (usize, Answer)>,
+{
+    let mut answers: Vec<Option<Answer>> = (0..names.len()).map(|_| None).collect();
+    let mut buffers = vec![String::new(); names.len()];
+    let mut current = 0;
+    if let Some(name) = names.first() {
+        println!("{}", format!("== {} ==", name).green());
+    }
+    while current < names.len() {
+        tokio::select! {
+            biased;
+            Some((i, text)) = progress.recv() => route(&mut buffers, current, i, text)?,
+            Some((i, answer)) = pending.next() => answers[i] = Some(answer),
+            else => break,
+        }
+        while let Some(answer) = answers.get(current).and_then(Option::as_ref) {
+            // 模型结束时它的片段可能还在通道中
+            while let Ok((i, text)) = progress.try_recv() {
+                route(&mut buffers, current, i, text)?;
+            }
+            if let Some(error) = &answer.error {
+                print!("{}", error.red());
+            }
+            println!("\n");
+            current += 1;
+            if let Some(name) = names.get(current) {
+                println!("{}", format!("== {} ==", name).green());
+                print!("{}", std::mem::take(&mut buffers[current]));
+                io::stdout().flush()?;
+            }
+        }
+    }
+    Ok(answers.into_iter().flatten().collect())
+}
+
+/// 终端宽度：显式设置的 $COLUMNS 优先，其次查询标准输出所在终端的大小
+fn terminal_width() -> usize {
+    std::env::var("COLUMNS")
+        .ok()
+        .and_then(|columns| columns.parse().ok())
+        .or_else(tty_width)
+        .unwrap_or(DEFAULT_WIDTH)
+}
+
+#[cfg(unix)]
+fn tty_width() -> Option<usize> {
+    let mut size: libc::winsize = unsafe { std::mem::zeroed() };
+    // 标准输出不是终端（例如重定向到文件）时 ioctl 失败
+    let ok = unsafe { libc::ioctl(libc::STDOUT_FILENO, libc::TIOCGWINSZ, &mut size) } == 0;
+    (ok && size.ws_col > 0).then_some(size.ws_col as usize)
+}
+
+#[cfg(not(unix))]
+fn tty_width() -> Option<usize> {
+    None
+}
+
+/// 按显示宽度折行，中日韩等全角字符占两列
+/// 尽量在空白处断开，超过一行的单词（包括没有空格的中文段落）按字符截断
+fn wrap(text: &str, width: usize) -> Vec<String> {
+    let mut lines = Vec::new();
+    for paragraph in text.lines() {
+        let mut line = String::new();
+        let mut used = 0;
+        for word in paragraph.split_whitespace() {
+            if used > 0 && used + 1 + word.width() > width {
+                lines.push(std::mem::take(&mut line));
+                used = 0;
+            }
+            if used > 0 {
+                line.push(' ');
+                used += 1;
+            }
+            for c in word.chars() {
+                let char_width = c.width().unwrap_or(0);
+                if used > 0 && used + char_width > width {
+                    lines.push(std::mem::take(&mut line));
+                    used = 0;
+                }
+                line.push(c);
+                used += char_width;
+            }
+        }
+        lines.push(line);
+    }
+    lines
+}
+
+/// 截取不超过指定显示宽度的前缀，并用空格补齐到该宽度
+fn fit(text: &str, width: usize) -> String {
+    let mut fitted = String::new();
+    let mut used = 0;
+    for c in text.chars() {
+        let char_width = c.width().unwrap_or(0);
+        if used + char_width > width {
+            break;
+        }
+        fitted.push(c);
+        used += char_width;
+    }
+    fitted.push_str(&" ".repeat(width - used));
+    fitted
+}
+
+fn print_columns(answers: &[Answer]) {
+    let count = answers.len().max(1);
+    let separators = COLUMN_SEPARATOR.width() * (count - 1);
+    let width = (terminal_width().saturating_sub(separators) / count).max(20);
+    let columns: Vec<Vec<String>> = answers.iter().map(|answer| wrap(answer.body().trim_end(), width)).collect();
+
+    let titles: Vec<String> = answers
+        .iter()
+        .map(|answer| {
+            let title = fit(&answer.title(), width);
+            match &answer.error {
+                Some(_) => title.red().to_string(),
+                None => title.green().to_string(),
+            }
+        })
+        .collect();
+    println!("{}", titles.join(COLUMN_SEPARATOR).trim_end());
+    let rule = "─".repeat(width);
+    println!("{}", vec![rule; count].join("─┼─"));
+
+    let height = columns.iter().map(Vec::len).max().unwrap_or(0);
+    for i in 0..height {
+        let line: Vec<String> = columns
+            .iter()
+            .map(|column| {
+                fit(column.get(i).map(String::as_str).unwrap_or_default(), width)
+            })
+            .collect();
+        println!("{}", line.join(COLUMN_SEPARATOR).trim_end());
+    }
+    println!();
+}
+
+fn format_duration(duration: Option<Duration>) -> String {
+    duration
+        .map(|d| format!("{}ms", d.as_millis()))
+        .unwrap_or_else(|| "-".to_string())
+}
+
+fn print_summary(answers: &[Answer], judged: bool) {
+    let mut header = vec!["MODEL", "LATENCY", "TTFT", "PROMPT", "COMPLETION", "TOTAL", "FINISH"];
+    if judged {
+        header.push("SCORE");
+    }
+    let tokens = |answer: &Answer, field: fn(&Usage) -> u64| {
+        answer
+            .usage
+            .as_ref()
+            .map(|usage| field(usage).to_string())
+            .unwrap_or_else(|| "-".to_string())
+    };
+    let rows: Vec<Vec<(String, Option<Color>)>> = answers
+        .iter()
+        .map(|answer| {
+            let finish = match (&answer.error, &answer.finish) {
+                (Some(_), _) => ("error".to_string(), Some(Color::Red)),
+                (None, Some(FinishReason::Stop)) => ("stop".to_string(), None),
+                (None, Some(FinishReason::Length)) => ("length".to_string(), Some(Color::Yellow)),
+                (None, Some(FinishReason::ContentFilter)) => ("filtered".to_string(), Some(Color::Yellow)),
+                (None, Some(FinishReason::ToolCalls)) => ("tool_calls".to_string(), None),
+                (None, Some(FinishReason::Other(reason))) => (reason.clone(), None),
+                (None, None) => ("-".to_string(), None),
+            };
+            let mut row = vec![
+                (answer.name.clone(), None),
+                (format_duration(answer.error.is_none().then_some(answer.latency)), None),
+                (format_duration(answer.ttft), None),
+                (tokens(answer, |usage| usage.prompt_tokens), None),
+                (tokens(answer, |usage| usage.completion_tokens), None),
+                (tokens(answer, |usage| usage.total_tokens), None),
+                finish,
+            ];
+            if judged {
+                row.push(match &answer.score {
+                    Some(score) => (format!("{}", score.score), Some(Color::Cyan)),
+                    None => ("-".to_string(), None),
+                });
+            }
+            row
+        })
+        .collect();
+    utils::print_table(&header, &rows);
+}
+
+/// 判分结果需要满足的格式
+fn judge_schema() -> Result<OutputSchema> {
+    OutputSchema::new(
+        "scores",
+        serde_json::json!({
+            "type": "object",
+            "required": ["scores"],
+            "properties": {
+                "scores": {
+                    "type": "array",
+                    "items": {
+                        "type": "object",
+                        "required": ["answer", "score"],
+                        "properties": {
+                            "answer": {"type": "string"},
+                            "score": {"type": "number"},
+                            "reason": {"type": "string"}
+                        }
+                    }
+                }
+            }
+        }),
+    )
+}
+
+/// 第 n 个回复的匿名编号：A、B … Z、AA、AB …
+fn label(n: usize) -> String {
+    let mut n = n + 1;
+    let mut label = Vec::new();
+    while n > 0 {
+        n -= 1;
+        label.push(b'A' + (n % 26) as u8);
+        n /= 26;
+    }
+    label.reverse();
+    String::from_utf8(label).unwrap_or_default()
+}
+
+/// 让评审模型为各个回复打分，回复以 A、B、C 匿名编号，避免模型名称影响评分
+async fn judge(provider: Provider, prompt: &str, answers: &mut [Answer], running: Arc<AtomicBool>) -> Result<()> {
+    let candidates: Vec<(String, usize)> = answers
+        .iter()
+        .enumerate()
+        .filter(|(_, answer)| answer.error.is_none())
+        .enumerate()
+        .map(|(n, (i, _))| (label(n), i))
+        .collect();
+    if candidates.is_empty() {
+        return Err(anyhow::anyhow!("no answers to judge"));
+    }
+
+    let mut content = format!("Question:\n{}\n", prompt);
+    for (label, i) in &candidates {
+        content.push_str(&format!("\nAnswer {}:\n{}\n", label, answers[*i].text.trim()));
+    }
+    let messages = vec![
+        Message::new("system", JUDGE_PROMPT.to_string()),
+        Message::new("user", content),
+    ];
+    let events = provider.with_json_mode(true).chat(messages, false, running).await?;
+    let mut chunks = llm_provider::text_stream(events);
+    let mut reply = String::new();
+    while let Some(chunk) = chunks.next().await {
+        reply.push_str(&chunk?);
+    }
+
+    let value = schema::parse_reply(&reply)
+        .map_err(|e| anyhow::anyhow!("invalid JSON from judge: {}", e))?;
+    let errors = judge_schema()?.validate(&value);
+    if !errors.is_empty() {
+        return Err(anyhow::anyhow!("unexpected judge response: {}", errors.join("; ")));
+    }
+    for item in value["scores"].as_array().into_iter().flatten() {
+        let label = item["answer"].as_str().unwrap_or_default().trim().trim_start_matches("Answer ");
+        if let Some((_, i)) = candidates.iter().find(|(candidate, _)| candidate == label) {
+            answers[*i].score = Some(Score {
+                score: item["score"].as_f64().unwrap_or_default(),
+                reason: item["reason"].as_str().unwrap_or_default().to_string(),
+            });
+        }
+    }
+    Ok(())
+}
+
+/// 同时向多个模型发送同一个问题，输出各自的回复、耗时与 token 用量
+/// judge_model: 用于给回复打分的模型
+pub async fn run(config: &Config, names: &[String], prompt: &str, params: &GenerationParams, judge_model: Option<&str>, layout: Layout, running: Arc<AtomicBool>) -> Result<()> {
+    let mut messages = Vec::new();
+    if let Some(system_prompt) = config.get_system_prompt() {
+        messages.push(Message::new("system", system_prompt.clone()));
+    }
+    messages.push(Message::new("user", prompt.to_string()));
+
+    // 先检查所有名称，避免部分请求已经发出后才报错
+    let mut requests = Vec::new();
+    for name in names {
+        let model = config
+            .get_model(name)
+            .ok_or_else(|| anyhow::anyhow!("model not found: {}", name))?;
+        let provider = config
+            .provider(model)
+            .map(|provider| provider.with_params(model.params.merge(params)));
+        requests.push((name.clone(), provider));
+    }
+    let judge_provider = match judge_model {
+        Some(name) => {
+            let model = config
+                .get_model(name)
+                .ok_or_else(|| anyhow::anyhow!("judge model not found: {}", name))?;
+            Some(config.provider(model)?.with_params(model.params.clone()))
+        }
+        None => None,
+    };
+
+    eprintln!("{}", format!("asking {} model(s)...", requests.len()).dimmed());
+    let (sender, receiver) = mpsc::unbounded_channel();
+    let pending: FuturesUnordered<_> = requests
+        .into_iter()
+        .enumerate()
+        .map(|(i, (name, provider))| {
+            let progress = (layout == Layout::Sequential).then(|| (i, sender.clone()));
+            let request = ask(name, provider, messages.clone(), config.stream, running.clone(), progress);
+            async move { (i, request.await) }
+        })
+        .collect();
+    drop(sender);
+    let mut answers = match layout {
+        Layout::Sequential => print_sequential(names, pending, receiver).await?,
+        Layout::Columns => {
+            let mut answers: Vec<(usize, Answer)> = pending.collect().await;
+            answers.sort_by_key(|(i, _)| *i);
+            let answers: Vec<Answer> = answers.into_iter().map(|(_, answer)| answer).collect();
+            print_columns(&answers);
+            answers
+        }
+    };
+
+    if let Some(provider) = judge_provider {
+        eprintln!("{}", "judging...".dimmed());
+        if let Err(e) = judge(provider, prompt, &mut answers, running).await {
+            eprintln!("{} {:#}", "judge failed:".yellow(), e);
+        }
+    }
+
+    let judged = answers.iter().any(|answer| answer.score.is_some());
+    print_summary(&answers, judged);
+    for answer in &answers {
+        if let Some(score) = answer.score.as_ref().filter(|score| !score.reason.is_empty()) {
+            println!("  {}: {}", answer.name.green(), score.reason);
+        }
+    }
+
+    let failed = answers.iter().filter(|answer| answer.error.is_some()).count();
+    if failed > 0 {
+        return Err(anyhow::anyhow!("{} of {} model(s) failed", failed, answers.len()));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn labels_continue_after_z() {
+        let labels: Vec<String> = [0, 1, 25, 26, 27, 51, 52, 701, 702].into_iter().map(label).collect();
+        assert_eq!(labels, ["A", "B", "Z", "AA", "AB", "AZ", "BA", "ZZ", "AAA"]);
+    }
+
+    #[test]
+    fn wrap_breaks_at_whitespace_and_splits_long_words() {
+        assert_eq!(wrap("one two three", 7), ["one two", "three"]);
+        assert_eq!(wrap("abcdefghij", 4), ["abcd", "efgh", "ij"]);
+        assert_eq!(wrap("a\n\nb", 10), ["a", "", "b"]);
+    }
+
+    #[test]
+    fn wrap_and_fit_use_display_width() {
+        assert_eq!(wrap("你好世界", 5), ["你好", "世界"]);
+        assert_eq!(wrap("答案 是 42", 6), ["答案", "是 42"]);
+        assert_eq!(wrap("中文English", 6), ["中文En", "glish"]);
+        assert_eq!(fit("模型A", 4), "模型");
+        assert_eq!(fit("模型A", 8), "模型A   ");
+    }
+}
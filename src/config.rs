@@ -1,31 +1,165 @@
-use anyhow::Result;
+use anyhow::{Context, Result};
 use colored::*;
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 use std::fs;
 use std::path::PathBuf;
+use std::time::Duration;
+use crate::cache::ResponseCache;
+use crate::llm_provider::{GenerationParams, HttpOptions, Provider};
+use crate::migrate::{self, Schema};
+use crate::project::{self, ProjectConfig};
 use crate::utils;
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ModelConfig {
     pub api_key: String,
     pub api_url: String,
     pub model: String,
+    /// 该模型使用的代理，覆盖全局设置，`none` 表示直连
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub proxy: Option<String>,
+    /// 跳过 TLS 证书校验，仅用于使用自签名证书的内部服务
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub insecure: bool,
+    /// 该模型的默认生成参数
+    #[serde(flatten, default)]
+    pub params: GenerationParams,
+}
+
+/// 常见服务商的默认接口地址与模型，`config model add` 未指定时使用
+pub struct ProviderPreset {
+    pub name: &'static str,
+    pub api_url: &'static str,
+    pub model: &'static str,
+    /// 是否需要 API Key，本地服务通常不需要
+    pub requires_key: bool,
+}
+
+pub const PROVIDER_PRESETS: &[ProviderPreset] = &[
+    ProviderPreset {
+        name: "openai",
+        api_url: "https://api.openai.com/v1/chat/completions",
+        model: "gpt-4o-mini",
+        requires_key: true,
+    },
+    ProviderPreset {
+        name: "deepseek",
+        api_url: "https://api.deepseek.com/v1/chat/completions",
+        model: "deepseek-chat",
+        requires_key: true,
+    },
+    ProviderPreset {
+        name: "openrouter",
+        api_url: "https://openrouter.ai/api/v1/chat/completions",
+        model: "openai/gpt-4o-mini",
+        requires_key: true,
+    },
+    ProviderPreset {
+        name: "groq",
+        api_url: "https://api.groq.com/openai/v1/chat/completions",
+        model: "llama-3.3-70b-versatile",
+        requires_key: true,
+    },
+    ProviderPreset {
+        name: "mistral",
+        api_url: "https://api.mistral.ai/v1/chat/completions",
+        model: "mistral-small-latest",
+        requires_key: true,
+    },
+    ProviderPreset {
+        name: "moonshot",
+        api_url: "https://api.moonshot.cn/v1/chat/completions",
+        model: "moonshot-v1-8k",
+        requires_key: true,
+    },
+    ProviderPreset {
+        name: "qwen",
+        api_url: "https://dashscope.aliyuncs.com/compatible-mode/v1/chat/completions",
+        model: "qwen-plus",
+        requires_key: true,
+    },
+    ProviderPreset {
+        name: "ollama",
+        api_url: "http://localhost:11434/v1/chat/completions",
+        model: "llama3.2",
+        requires_key: false,
+    },
+];
+
+pub fn provider_preset(name: &str) -> Option<&'static ProviderPreset> {
+    PROVIDER_PRESETS.iter().find(|preset| preset.name == name)
 }
 
-#[derive(Debug, Default, Serialize, Deserialize)]
+#[derive(Debug, Serialize, Deserialize)]
 pub struct Config {
+    /// 配置文件格式版本
+    #[serde(default)]
+    pub version: u32,
     #[serde(default)]
     pub models: HashMap<String, ModelConfig>,
     #[serde(default)]
     pub current_model: Option<String>,
-    #[serde(default = "default_model")]
+    #[serde(default = "default_system_prompt")]
     pub system_prompt: Option<String>,
     #[serde(default = "default_stream")]
     pub stream: bool,
+    /// 回复因长度被截断时自动请求续写的最大次数，0 表示关闭
+    #[serde(default, skip_serializing_if = "is_zero")]
+    pub auto_continue: u32,
+    /// 不显示推理模型的思考过程
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub hide_reasoning: bool,
+    /// 额外信任的 CA 证书文件（PEM），用于企业内部证书
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ca_bundle: Option<PathBuf>,
+    /// 全局代理，未设置时使用 HTTPS_PROXY 等环境变量
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub proxy: Option<String>,
+    /// 记录原始 HTTP 请求与响应的目录，用于排查服务商的问题，未设置时不记录
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub log_dir: Option<PathBuf>,
+    /// 响应缓存设置，未设置时不缓存
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cache: Option<CacheConfig>,
+    /// 项目级配置，读取时覆盖用户配置，不会被保存
+    #[serde(skip)]
+    pub project: Option<ProjectConfig>,
+}
+
+/// 响应缓存设置
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CacheConfig {
+    /// 是否缓存回复，相同的模型、消息与参数直接返回缓存的回复
+    #[serde(default)]
+    pub enabled: bool,
+    /// 缓存有效期（秒）
+    #[serde(default = "default_cache_ttl")]
+    pub ttl: u64,
+    /// 缓存目录的大小上限（MB），超出时删除最旧的缓存
+    #[serde(default = "default_cache_max_size")]
+    pub max_size_mb: u64,
 }
 
-fn default_model() -> Option<String> {
+impl Default for CacheConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            ttl: default_cache_ttl(),
+            max_size_mb: default_cache_max_size(),
+        }
+    }
+}
+
+fn default_cache_ttl() -> u64 {
+    7 * 24 * 60 * 60
+}
+
+fn default_cache_max_size() -> u64 {
+    100
+}
+
+fn default_system_prompt() -> Option<String> {
     Some("your are a AI assistant".to_string())
 }
 
@@ -33,25 +167,46 @@ fn default_stream() -> bool {
     true
 }
 
+fn is_zero(value: &u32) -> bool {
+    *value == 0
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            version: migrate::CONFIG_VERSION,
+            models: HashMap::new(),
+            current_model: None,
+            system_prompt: None,
+            stream: false,
+            auto_continue: 0,
+            hide_reasoning: false,
+            ca_bundle: None,
+            proxy: None,
+            log_dir: None,
+            cache: None,
+            project: None,
+        }
+    }
+}
+
 impl Config {
     pub fn load() -> Result<Self> {
         if let Some(path) = Self::get_path() {
-            let mut config = if path.exists() {
-                let content = fs::read_to_string(&path)?;
-                toml::from_str(&content)?
-            } else {
-                let config = Config::default();
-                config.save()?;
-                config
-            };
+            let _lock = utils::lock_file(&path)?;
+            let mut config = Self::read(&path)?;
 
-            // 如果没有当前模型，添加一个默认的 OpenAI 配置
+            // 如果没有当前模型，按 openai 预设添加默认配置，与 `config model add openai` 保持一致
             if config.current_model.is_none() {
                 let default_name = "openai";
+                let preset = provider_preset(default_name).expect("openai preset exists");
                 let model_config = ModelConfig {
                     api_key: String::new(),
-                    api_url: "https://api.openai.com/v1/chat/completions".to_string(),
-                    model: "gpt-3.5-turbo".to_string(),
+                    api_url: preset.api_url.to_string(),
+                    model: preset.model.to_string(),
+                    proxy: None,
+                    insecure: false,
+                    params: GenerationParams::default(),
                 };
                 config.models.insert(default_name.to_string(), model_config);
                 config.current_model = Some(default_name.to_string());
@@ -59,18 +214,39 @@ impl Config {
 
                 println!("提示：已添加默认 OpenAI 配置，请使用以下命令设置 API Key：");
                 println!(
-                    "  gpt config model add {} <your-api-key>",
+                    "  gpt config model set {} --key <your-api-key>",
                     default_name.green()
                 );
             }
 
+            config.project = ProjectConfig::discover().map(|mut project| {
+                let mut models = std::mem::take(&mut project.models);
+                project::drop_shadowed(&project, "model", &mut models, |name| config.models.contains_key(name));
+                project.models = models;
+                project
+            });
             Ok(config)
         } else {
             Ok(Config::default())
         }
     }
 
-    pub fn save(&self) -> Result<()> {
+    /// 读取用户配置文件，不存在时创建默认配置，调用方需持有文件锁
+    fn read(path: &PathBuf) -> Result<Self> {
+        if path.exists() {
+            let content = fs::read_to_string(path)?;
+            let content = migrate::upgrade(Schema::Config, path, &content, true)?;
+            toml::from_str(&content)
+                .with_context(|| format!("invalid config file {}", path.display()))
+        } else {
+            let config = Config::default();
+            config.save()?;
+            Ok(config)
+        }
+    }
+
+    /// 保存到用户配置文件，调用方需持有文件锁，修改配置请使用 update
+    fn save(&self) -> Result<()> {
         if let Some(path) = Self::get_path() {
             let content = toml::to_string_pretty(self)?;
             utils::save_file(&content, &path)?;
@@ -78,6 +254,21 @@ impl Config {
         Ok(())
     }
 
+    /// 在文件锁内重新读取用户配置、修改并保存
+    /// 避免多个 gpt 进程同时修改时互相覆盖，成功后 self 更新为最新内容
+    fn update<T>(&mut self, f: impl FnOnce(&mut Self) -> Result<T>) -> Result<T> {
+        let Some(path) = Self::get_path() else {
+            return f(self);
+        };
+        let _lock = utils::lock_file(&path)?;
+        let mut config = Self::read(&path)?;
+        config.project = self.project.clone();
+        let result = f(&mut config)?;
+        config.save()?;
+        *self = config;
+        Ok(result)
+    }
+
     pub fn get_path() -> Option<PathBuf> {
         let mut path = utils::get_config_dir()?;
         path.push("config.toml");
@@ -91,69 +282,225 @@ impl Config {
         Ok(())
     }
 
+    /// 添加或替换模型
+    /// 未指定的接口地址和模型取自服务商预设：优先使用 provider，其次是与模型同名的预设，
+    /// 两者都没有且未指定地址时按 openai 处理
     pub fn add_model(
         &mut self,
         name: String,
-        api_key: String,
-        api_url: String,
-        model: String,
+        provider: Option<&str>,
+        api_key: Option<String>,
+        api_url: Option<String>,
+        model: Option<String>,
     ) -> Result<()> {
+        let names = || PROVIDER_PRESETS.iter().map(|p| p.name).collect::<Vec<_>>().join(", ");
+        let preset = match provider {
+            Some(provider) => Some(
+                provider_preset(provider)
+                    .ok_or_else(|| anyhow::anyhow!("unknown provider: {} (available: {})", provider, names()))?,
+            ),
+            None => provider_preset(&name),
+        };
+        // 未知服务商必须指定 --url，避免把其他服务商的 key 发送到 OpenAI
+        let api_url = api_url.or_else(|| preset.map(|p| p.api_url.to_string())).ok_or_else(|| {
+            anyhow::anyhow!(
+                "unknown provider: {}, use --provider <preset> or --url for custom providers (available: {})",
+                name,
+                names()
+            )
+        })?;
+        let model = model
+            .or_else(|| preset.map(|p| p.model.to_string()))
+            .ok_or_else(|| anyhow::anyhow!("--model is required for custom providers"))?;
+        let api_key = api_key.unwrap_or_default();
+        let missing_key = api_key.is_empty() && preset.is_none_or(|p| p.requires_key);
+
         let model_config = ModelConfig {
             api_key,
             api_url,
             model,
+            proxy: None,
+            insecure: false,
+            params: GenerationParams::default(),
         };
-        self.models.insert(name.clone(), model_config);
-        // 如果是第一个模型，设置为当前模型
-        if self.current_model.is_none() {
-            self.current_model = Some(name.clone());
+        let replaced = self.update(|config| {
+            let replaced = config.models.insert(name.clone(), model_config).is_some();
+            // 如果是第一个模型，设置为当前模型
+            if config.current_model.is_none() {
+                config.current_model = Some(name.clone());
+            }
+            Ok(replaced)
+        })?;
+        if replaced {
+            println!("model replaced: {}", name.green());
+        } else {
+            println!("model added: {}", name.green());
         }
-        self.save()?;
-        println!("model added: {}", name.green());
+        if missing_key {
+            println!(
+                "API key not set, use `gpt config model set {} --key <your-api-key>`",
+                name
+            );
+        }
+        Ok(())
+    }
+
+    /// 只修改指定的字段，生成参数与已有参数合并
+    pub fn set_model(
+        &mut self,
+        name: &str,
+        api_key: Option<String>,
+        api_url: Option<String>,
+        model: Option<String>,
+        params: &GenerationParams,
+    ) -> Result<()> {
+        if api_key.is_none() && api_url.is_none() && model.is_none() && params.is_empty() {
+            return Err(anyhow::anyhow!("nothing to change, pass at least one of --key, --url, --model or a generation parameter"));
+        }
+        self.update(|config| {
+            let entry = config.user_model_mut(name)?;
+            if let Some(api_key) = api_key {
+                entry.api_key = api_key;
+            }
+            if let Some(api_url) = api_url {
+                entry.api_url = api_url;
+            }
+            if let Some(model) = model {
+                entry.model = model;
+            }
+            entry.params = entry.params.merge(params);
+            Ok(())
+        })?;
+        println!("model updated: {}", name.green());
+        Ok(())
+    }
+
+    /// 重命名模型，当前模型会同步更新
+    pub fn rename_model(&mut self, name: &str, new_name: &str) -> Result<()> {
+        self.update(|config| {
+            config.check_new_model_name(new_name)?;
+            config.user_model_mut(name)?;
+            let model_config = config.models.remove(name).expect("model exists");
+            config.models.insert(new_name.to_string(), model_config);
+            if config.current_model.as_deref() == Some(name) {
+                config.current_model = Some(new_name.to_string());
+            }
+            Ok(())
+        })?;
+        println!("model renamed: {} -> {}", name, new_name.green());
+        Ok(())
+    }
+
+    /// 复制模型配置，项目级模型也可以复制到用户配置中
+    pub fn copy_model(&mut self, name: &str, new_name: &str) -> Result<()> {
+        self.update(|config| {
+            config.check_new_model_name(new_name)?;
+            let model_config = config
+                .get_model(name)
+                .cloned()
+                .ok_or_else(|| anyhow::anyhow!("model not found: {}", name))?;
+            config.models.insert(new_name.to_string(), model_config);
+            Ok(())
+        })?;
+        println!("model copied: {} -> {}", name, new_name.green());
         Ok(())
     }
 
+    fn check_new_model_name(&self, name: &str) -> Result<()> {
+        if name.trim().is_empty() {
+            return Err(anyhow::anyhow!("model name must not be empty"));
+        }
+        if self.models.contains_key(name) {
+            return Err(anyhow::anyhow!("model already exists: {}", name));
+        }
+        Ok(())
+    }
+
+    /// 获取可修改的用户级模型，项目级模型只能在项目配置中修改
+    fn user_model_mut(&mut self, name: &str) -> Result<&mut ModelConfig> {
+        if !self.models.contains_key(name)
+            && self.project.as_ref().is_some_and(|p| p.models.contains_key(name))
+        {
+            return Err(anyhow::anyhow!(
+                "model {} is defined in project config, edit it there instead",
+                name
+            ));
+        }
+        self.models
+            .get_mut(name)
+            .ok_or_else(|| anyhow::anyhow!("model not found: {}", name))
+    }
+
     pub fn remove_model(&mut self, name: &str) -> Result<()> {
-        if self.models.remove(name).is_some() {
+        self.update(|config| {
+            if config.models.remove(name).is_none() {
+                return Err(anyhow::anyhow!("model not found: {}", name));
+            }
             // 如果删除的是当前模型，重置当前模型
-            if self.current_model.as_deref() == Some(name) {
-                self.current_model = self.models.keys().next().map(|k| k.to_string());
+            if config.current_model.as_deref() == Some(name) {
+                config.current_model = config.models.keys().next().map(|k| k.to_string());
             }
-            self.save()?;
-            println!("model removed: {}", name.green());
             Ok(())
-        } else {
-            Err(anyhow::anyhow!("model not found: {}", name))
-        }
+        })?;
+        println!("model removed: {}", name.green());
+        Ok(())
     }
 
     pub fn set_current_model(&mut self, name: &str) -> Result<()> {
-        if self.models.contains_key(name) {
-            self.current_model = Some(name.to_string());
-            self.save()?;
-            println!("current model set to: {}", name.green());
+        self.update(|config| {
+            // 只在项目中定义的模型写入用户配置后，离开项目目录就无法使用
+            if !config.models.contains_key(name) {
+                return Err(match config.project.as_ref().filter(|p| p.models.contains_key(name)) {
+                    Some(project) => anyhow::anyhow!(
+                        "model '{}' is only defined in the project config {}, set current_model there instead",
+                        name,
+                        project.path.display()
+                    ),
+                    None => anyhow::anyhow!("model not found: {}", name),
+                });
+            }
+            config.current_model = Some(name.to_string());
             Ok(())
-        } else {
-            Err(anyhow::anyhow!("model not found: {}", name))
-        }
+        })?;
+        println!("current model set to: {}", name.green());
+        Ok(())
     }
 
     pub fn list_models(&self) {
-        if self.models.is_empty() {
+        let project_models = self.project.as_ref().map(|p| &p.models);
+        if self.models.is_empty() && project_models.is_none_or(|m| m.is_empty()) {
             println!("no models configured yet");
             return;
         }
 
+        let current_name = self.get_current_model().map(|(name, _)| name);
         println!("available models:");
-        for (name, config) in &self.models {
-            let current = if self.current_model.as_deref() == Some(name) {
+        let user_models = self
+            .models
+            .iter()
+            .filter(|(name, _)| project_models.is_none_or(|m| !m.contains_key(*name)))
+            .map(|(name, config)| (name, config, false));
+        let project_models = project_models
+            .into_iter()
+            .flatten()
+            .map(|(name, config)| (name, config, true));
+        for (name, config, from_project) in user_models.chain(project_models) {
+            let current = if current_name == Some(name.as_str()) {
                 " (current)".yellow()
             } else {
                 "".clear()
             };
-            println!("- {}{}", name.green(), current);
+            let source = if from_project {
+                " (project)".cyan()
+            } else {
+                "".clear()
+            };
+            println!("- {}{}{}", name.green(), source, current);
             println!("  API URL: {}", config.api_url);
             println!("  Model: {}", config.model);
+            if !config.params.is_empty() {
+                println!("  Params: {}", config.params);
+            }
             println!(
                 "  API Key: {}",
                 if config.api_key.is_empty() {
@@ -165,22 +512,92 @@ impl Config {
         }
     }
 
+    /// 模型请求使用的网络设置：模型的代理优先于全局代理
+    pub fn http_options(&self, model: &ModelConfig) -> HttpOptions {
+        HttpOptions {
+            ca_bundle: self.ca_bundle.clone(),
+            proxy: model.proxy.clone().or_else(|| self.proxy.clone()),
+            insecure: model.insecure,
+        }
+    }
+
+    /// 按模型配置创建 provider，包含接口地址、默认生成参数、网络设置、响应缓存与请求日志
+    pub fn provider(&self, model: &ModelConfig) -> Result<Provider> {
+        Provider::new(model.api_key.clone())
+            .with_url(model.api_url.clone())
+            .with_model(model.model.clone())
+            .with_params(model.params.clone())
+            .with_http_options(&self.http_options(model))
+            .map(|provider| {
+                provider
+                    .with_cache(self.response_cache().filter(|_| self.cache_enabled()))
+                    .with_log_dir(self.log_dir.clone())
+            })
+    }
+
+    /// 配置中是否开启了响应缓存，`--no-cache` 会在运行时关闭
+    pub fn cache_enabled(&self) -> bool {
+        self.cache.as_ref().is_some_and(|cache| cache.enabled)
+    }
+
+    /// 按缓存设置打开缓存目录，不检查是否开启
+    pub fn response_cache(&self) -> Option<ResponseCache> {
+        let settings = self.cache.clone().unwrap_or_default();
+        Some(ResponseCache::new(
+            ResponseCache::default_dir()?,
+            Duration::from_secs(settings.ttl),
+            settings.max_size_mb.saturating_mul(1024 * 1024),
+        ))
+    }
+
+    /// 按名称查找模型，项目配置只能新增模型，同名时使用用户配置
+    pub fn get_model(&self, name: &str) -> Option<&ModelConfig> {
+        self.models
+            .get(name)
+            .or_else(|| self.project.as_ref().and_then(|p| p.models.get(name)))
+    }
+
+    /// 所有可用的模型名称，包括项目配置中的模型，按名称排序
+    pub fn model_names(&self) -> Vec<String> {
+        let mut names: Vec<String> = self.models.keys().cloned().collect();
+        if let Some(project) = &self.project {
+            names.extend(project.models.keys().cloned());
+        }
+        names.sort();
+        names.dedup();
+        names
+    }
+
     pub fn get_current_model(&self) -> Option<(&str, &ModelConfig)> {
-        self.current_model
+        self.project
             .as_ref()
-            .and_then(|name| self.models.get(name).map(|config| (name.as_str(), config)))
+            .and_then(|p| p.current_model.as_ref())
+            .or(self.current_model.as_ref())
+            .and_then(|name| self.get_model(name).map(|config| (name.as_str(), config)))
+    }
+
+    /// 生效的系统提示词，项目配置优先
+    pub fn get_system_prompt(&self) -> Option<&String> {
+        self.project
+            .as_ref()
+            .and_then(|p| p.system_prompt.as_ref())
+            .or(self.system_prompt.as_ref())
     }
 
     pub fn set_system_prompt(&mut self, prompt: Option<String>) -> Result<()> {
-        self.system_prompt = prompt;
-        self.save()?;
+        self.update(|config| {
+            config.system_prompt = prompt;
+            Ok(())
+        })?;
         println!("system prompt updated");
         Ok(())
     }
 
     pub fn set_stream(&mut self, enabled: bool) -> Result<()> {
-        self.stream = enabled;
-        self.save()?;
+        self.update(|config| {
+            config.stream = enabled;
+            Ok(())
+        })?;
         println!(
             "stream output {}",
             if enabled {
@@ -191,4 +608,233 @@ impl Config {
         );
         Ok(())
     }
+
+    /// 读取点分路径对应的用户配置项，例如 `models.openai.model`
+    /// 读取整个表时其中的敏感字段会被隐藏，直接读取该字段才会显示原值
+    pub fn get_value(&self, path: &str) -> Result<toml::Value> {
+        let keys = parse_key_path(path)?;
+        let root = toml::Value::try_from(self)?;
+        let mut value = lookup(&root, &keys)
+            .cloned()
+            .ok_or_else(|| anyhow::anyhow!("config key not set: {}", path))?;
+        redact(&mut value);
+        Ok(value)
+    }
+
+    /// 设置点分路径对应的配置项，取值按配置结构做类型检查
+    /// 已有的配置项按原类型解析，新配置项先按 TOML 字面量解析，失败时视为字符串
+    pub fn set_value(&mut self, path: &str, raw: &str) -> Result<()> {
+        let keys = parse_key_path(path)?;
+        check_writable(path, &keys)?;
+        self.update(|config| {
+            let root = toml::Value::try_from(&*config)?;
+            let candidates = match lookup(&root, &keys) {
+                Some(existing) => vec![convert_value(path, raw, existing)?],
+                None => parse_literal(raw),
+            };
+            let mut error = None;
+            for candidate in candidates {
+                let mut value = root.clone();
+                insert(&mut value, &keys, candidate)?;
+                match config.replace_with(value, path, &keys) {
+                    Ok(()) => return Ok(()),
+                    Err(e) => error = Some(e),
+                }
+            }
+            Err(error.unwrap_or_else(|| anyhow::anyhow!("invalid value for {}", path)))
+        })?;
+        println!("{} updated", path.green());
+        Ok(())
+    }
+
+    /// 删除点分路径对应的配置项，必填项不能删除
+    pub fn unset_value(&mut self, path: &str) -> Result<()> {
+        let keys = parse_key_path(path)?;
+        check_writable(path, &keys)?;
+        self.update(|config| {
+            let mut root = toml::Value::try_from(&*config)?;
+            let (last, parents) = keys.split_last().expect("key path is not empty");
+            let removed = parents
+                .iter()
+                .try_fold(&mut root, |value, key| value.get_mut(key.as_str()))
+                .and_then(|value| value.as_table_mut())
+                .and_then(|table| table.remove(last));
+            if removed.is_none() {
+                return Err(anyhow::anyhow!("config key not set: {}", path));
+            }
+            let mut new_config: Config = root
+                .try_into()
+                .map_err(|e: toml::de::Error| anyhow::anyhow!("can not unset {}: {}", path, e.message()))?;
+            new_config.project = config.project.take();
+            *config = new_config;
+            Ok(())
+        })?;
+        println!("{} unset", path.green());
+        Ok(())
+    }
+
+    /// 用修改后的配置替换当前配置，无法反序列化或路径不属于配置结构时返回错误
+    fn replace_with(&mut self, value: toml::Value, path: &str, keys: &[String]) -> Result<()> {
+        let mut new_config: Config = value
+            .try_into()
+            .map_err(|e: toml::de::Error| anyhow::anyhow!("invalid value for {}: {}", path, e.message()))?;
+        // 未知字段会在反序列化时被忽略，重新序列化后检查路径是否仍然存在
+        let check = toml::Value::try_from(&new_config)?;
+        if lookup(&check, keys).is_none() {
+            return Err(anyhow::anyhow!("unknown config key: {}", path));
+        }
+        new_config.project = self.project.take();
+        *self = new_config;
+        Ok(())
+    }
+
+    /// 导出用户配置，API Key 等敏感字段会被替换
+    pub fn to_redacted_value(&self) -> Result<toml::Value> {
+        let mut value = toml::Value::try_from(self)?;
+        redact(&mut value);
+        Ok(value)
+    }
+}
+
+/// 输出配置时需要隐藏的字段
+const SECRET_KEYS: &[&str] = &["api_key"];
+
+fn redact(value: &mut toml::Value) {
+    if let Some(table) = value.as_table_mut() {
+        for (key, value) in table.iter_mut() {
+            match value {
+                toml::Value::String(secret) if SECRET_KEYS.contains(&key.as_str()) => {
+                    if !secret.is_empty() {
+                        *secret = "********".to_string();
+                    }
+                }
+                _ => redact(value),
+            }
+        }
+    }
+}
+
+/// 解析点分路径，包含 `.` 的名称可以用双引号包裹，例如 `models."gpt-4.1".model`
+fn parse_key_path(path: &str) -> Result<Vec<String>> {
+    let mut keys = Vec::new();
+    let mut current = String::new();
+    let mut quoted = false;
+    for c in path.chars() {
+        match c {
+            '"' => quoted = !quoted,
+            '.' if !quoted => keys.push(std::mem::take(&mut current)),
+            _ => current.push(c),
+        }
+    }
+    keys.push(current);
+    if quoted || keys.iter().any(|key| key.is_empty()) {
+        return Err(anyhow::anyhow!("invalid config key: '{}'", path));
+    }
+    Ok(keys)
+}
+
+fn check_writable(path: &str, keys: &[String]) -> Result<()> {
+    if keys[0] == "version" {
+        return Err(anyhow::anyhow!("{} is managed by gpt-shell and can not be changed", path));
+    }
+    Ok(())
+}
+
+fn lookup<'a>(value: &'a toml::Value, keys: &[String]) -> Option<&'a toml::Value> {
+    keys.iter().try_fold(value, |value, key| value.get(key.as_str()))
+}
+
+/// 写入配置项，缺少的中间表会被创建
+fn insert(root: &mut toml::Value, keys: &[String], new_value: toml::Value) -> Result<()> {
+    let (last, parents) = keys.split_last().expect("key path is not empty");
+    let mut value = root;
+    for key in parents {
+        let table = value
+            .as_table_mut()
+            .ok_or_else(|| anyhow::anyhow!("'{}' is not a table", key))?;
+        value = table
+            .entry(key.clone())
+            .or_insert_with(|| toml::Value::Table(toml::map::Map::new()));
+    }
+    value
+        .as_table_mut()
+        .ok_or_else(|| anyhow::anyhow!("'{}' is not a table", last))?
+        .insert(last.clone(), new_value);
+    Ok(())
+}
+
+/// 按已有配置项的类型解析输入
+fn convert_value(path: &str, raw: &str, existing: &toml::Value) -> Result<toml::Value> {
+    let invalid = |expected: &str| anyhow::anyhow!("invalid value for {}: expected {}, got '{}'", path, expected, raw);
+    match existing {
+        toml::Value::String(_) => Ok(toml::Value::String(raw.to_string())),
+        toml::Value::Boolean(_) => raw.parse().map(toml::Value::Boolean).map_err(|_| invalid("true or false")),
+        toml::Value::Integer(_) => raw.parse().map(toml::Value::Integer).map_err(|_| invalid("an integer")),
+        toml::Value::Float(_) => raw.parse().map(toml::Value::Float).map_err(|_| invalid("a number")),
+        toml::Value::Array(_) => match parse_literal(raw).into_iter().next() {
+            Some(toml::Value::Array(array)) => Ok(toml::Value::Array(array)),
+            _ => Ok(toml::Value::Array(vec![toml::Value::String(raw.to_string())])),
+        },
+        toml::Value::Table(_) => Err(anyhow::anyhow!("{} is a table, set its keys one by one", path)),
+        toml::Value::Datetime(_) => Err(invalid("a datetime")),
+    }
+}
+
+/// 新配置项的候选取值：TOML 字面量与原始字符串
+fn parse_literal(raw: &str) -> Vec<toml::Value> {
+    let mut candidates = Vec::new();
+    if let Ok(mut table) = toml::from_str::<toml::Table>(&format!("value = {}", raw)) {
+        if let Some(value) = table.remove("value") {
+            candidates.push(value);
+        }
+    }
+    if !matches!(candidates.first(), Some(toml::Value::String(s)) if s == raw) {
+        candidates.push(toml::Value::String(raw.to_string()));
+    }
+    candidates
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn keys(path: &str) -> Vec<String> {
+        parse_key_path(path).unwrap()
+    }
+
+    #[test]
+    fn key_path_splits_on_dots() {
+        assert_eq!(keys("current_model"), ["current_model"]);
+        assert_eq!(keys("models.gpt4.temperature"), ["models", "gpt4", "temperature"]);
+    }
+
+    #[test]
+    fn key_path_keeps_dots_inside_quotes() {
+        assert_eq!(keys("models.\"gpt-4.1\".model"), ["models", "gpt-4.1", "model"]);
+        assert_eq!(keys("\"a.b\""), ["a.b"]);
+    }
+
+    #[test]
+    fn key_path_rejects_empty_segments_and_open_quotes() {
+        for path in ["", ".", "models.", ".models", "models..model", "models.\"gpt-4.1.model", "\"\""] {
+            assert!(parse_key_path(path).is_err(), "{:?} should be rejected", path);
+        }
+    }
+
+    #[test]
+    fn values_follow_the_existing_type() {
+        let existing = toml::Value::Integer(1);
+        assert_eq!(convert_value("k", "42", &existing).unwrap(), toml::Value::Integer(42));
+        assert!(convert_value("k", "abc", &existing).is_err());
+        let existing = toml::Value::String(String::new());
+        assert_eq!(convert_value("k", "42", &existing).unwrap(), toml::Value::String("42".into()));
+    }
+
+    #[test]
+    fn insert_creates_missing_tables() {
+        let mut root = toml::Value::Table(Default::default());
+        insert(&mut root, &keys("models.\"gpt-4.1\".model"), toml::Value::String("x".into())).unwrap();
+        assert_eq!(root["models"]["gpt-4.1"]["model"].as_str(), Some("x"));
+        assert!(insert(&mut root, &keys("models.\"gpt-4.1\".model.deep"), toml::Value::Integer(1)).is_err());
+    }
 }
@@ -0,0 +1,365 @@
+use crate::agents::{Agent, AgentManager};
+use crate::bots::{self, BotsConfig, ReservedFlags};
+use crate::config::Config;
+use crate::migrate::{self, Schema};
+use crate::project::ProjectConfig;
+use crate::registry::RegistryConfig;
+use crate::utils;
+use anyhow::Result;
+use colored::*;
+use serde::de::DeserializeOwned;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+#[derive(Debug, PartialEq)]
+enum Level {
+    Error,
+    Warning,
+}
+
+#[derive(Debug)]
+struct Issue {
+    level: Level,
+    line: Option<usize>,
+    message: String,
+}
+
+/// 单个文件的检查结果
+struct FileReport {
+    path: PathBuf,
+    content: String,
+    issues: Vec<Issue>,
+}
+
+impl FileReport {
+    fn read(path: &Path) -> Self {
+        let mut report = Self {
+            path: path.to_path_buf(),
+            content: String::new(),
+            issues: Vec::new(),
+        };
+        match fs::read_to_string(path) {
+            Ok(content) => report.content = content,
+            Err(e) => report.error(None, format!("can not read file: {}", e)),
+        }
+        report
+    }
+
+    fn error(&mut self, line: Option<usize>, message: String) {
+        self.issues.push(Issue {
+            level: Level::Error,
+            line,
+            message,
+        });
+    }
+
+    fn warning(&mut self, line: Option<usize>, message: String) {
+        self.issues.push(Issue {
+            level: Level::Warning,
+            line,
+            message,
+        });
+    }
+
+    /// 查找以 pattern 开头的第一行，返回从 1 开始的行号
+    fn line_of(&self, pattern: &str) -> Option<usize> {
+        self.content
+            .lines()
+            .position(|line| line.trim_start().starts_with(pattern))
+            .map(|i| i + 1)
+    }
+
+    fn line_of_offset(&self, offset: usize) -> usize {
+        self.content[..offset.min(self.content.len())].matches('\n').count() + 1
+    }
+
+    /// 检查版本号并解析为指定类型，失败时记录带行号的错误
+    fn parse<T: DeserializeOwned>(&mut self, schema: Schema) -> Option<T> {
+        let doc: toml_edit::DocumentMut = match self.content.parse() {
+            Ok(doc) => doc,
+            Err(e) => {
+                let e: toml_edit::TomlError = e;
+                let line = e.span().map(|span| self.line_of_offset(span.start));
+                self.error(line, format!("syntax error: {}", e.message()));
+                return None;
+            }
+        };
+        match migrate::file_version(doc.as_table()) {
+            Ok(version) if version > schema.current_version() => {
+                self.error(
+                    self.line_of("version"),
+                    format!(
+                        "version {} is newer than supported version {}, please update gpt-shell",
+                        version,
+                        schema.current_version()
+                    ),
+                );
+                return None;
+            }
+            Ok(version) if version < schema.current_version() => {
+                self.warning(
+                    self.line_of("version"),
+                    format!(
+                        "version {} is outdated, it will be upgraded to {} on next run",
+                        version,
+                        schema.current_version()
+                    ),
+                );
+            }
+            Ok(_) => {}
+            Err(e) => {
+                self.error(self.line_of("version"), e.to_string());
+                return None;
+            }
+        }
+
+        let content = match migrate::upgrade(schema, &self.path, &self.content, false) {
+            Ok(content) => content,
+            Err(e) => {
+                self.error(None, format!("{:#}", e));
+                return None;
+            }
+        };
+        match toml::from_str(&content) {
+            Ok(value) => Some(value),
+            Err(e) => {
+                // 升级后的内容位置会变化，尽量用原文件定位行号
+                let (message, line) = match toml::from_str::<T>(&self.content) {
+                    Err(original) => (
+                        original.message().to_string(),
+                        original.span().map(|span| self.line_of_offset(span.start)),
+                    ),
+                    Ok(_) => (e.message().to_string(), None),
+                };
+                self.error(line, message);
+                None
+            }
+        }
+    }
+
+    fn print(&self) -> (usize, usize) {
+        println!("{}", self.path.display());
+        if self.issues.is_empty() {
+            println!("  {}", "ok".green());
+        }
+        let mut errors = 0;
+        let mut warnings = 0;
+        for issue in &self.issues {
+            let location = issue
+                .line
+                .map(|line| format!(" (line {})", line))
+                .unwrap_or_default();
+            match issue.level {
+                Level::Error => {
+                    errors += 1;
+                    println!("  {}{}: {}", "error".red(), location, issue.message);
+                }
+                Level::Warning => {
+                    warnings += 1;
+                    println!("  {}{}: {}", "warning".yellow(), location, issue.message);
+                }
+            }
+        }
+        (errors, warnings)
+    }
+}
+
+fn check_config(path: &Path) -> (FileReport, Option<Config>) {
+    let mut report = FileReport::read(path);
+    let config: Option<Config> = report.parse(Schema::Config);
+    if let Some(config) = &config {
+        if let Some(current) = &config.current_model {
+            if !config.models.contains_key(current) {
+                report.error(
+                    report.line_of("current_model"),
+                    format!("current_model: model '{}' is not configured", current),
+                );
+            }
+        }
+        if let Some(ca_bundle) = &config.ca_bundle {
+            if !ca_bundle.is_file() {
+                report.error(
+                    report.line_of("ca_bundle"),
+                    format!("ca_bundle: file not found: {}", ca_bundle.display()),
+                );
+            }
+        }
+        for (name, model) in &config.models {
+            if model.insecure {
+                report.warning(
+                    report.line_of(&format!("[models.{}]", name)),
+                    format!("models.{}.insecure: TLS certificate verification is disabled", name),
+                );
+            }
+            let line = report.line_of(&format!("[models.{}]", name));
+            if !model.api_url.starts_with("http://") && !model.api_url.starts_with("https://") {
+                report.error(
+                    line,
+                    format!("models.{}.api_url: '{}' is not an http(s) url", name, model.api_url),
+                );
+            }
+            if model.model.trim().is_empty() {
+                report.error(line, format!("models.{}.model: must not be empty", name));
+            }
+            if model.api_key.is_empty() {
+                report.warning(line, format!("models.{}.api_key: not set", name));
+            }
+        }
+    }
+    (report, config)
+}
+
+fn check_bots(path: &Path, config: Option<&Config>, reserved: &ReservedFlags) -> FileReport {
+    let mut report = FileReport::read(path);
+    let Some(bots_config) = report.parse::<BotsConfig>(Schema::Bots) else {
+        return report;
+    };
+    for (name, bot) in &bots_config.bots {
+        let line = report.line_of(&format!("[bots.{}]", name));
+        if let Err(e) = bot.validate(&format!("bots.{}.", name)) {
+            for message in e.to_string().lines() {
+                report.error(line, message.to_string());
+            }
+        }
+        if bot.name != *name {
+            report.warning(
+                line,
+                format!("bots.{}.name: '{}' differs from the table name", name, bot.name),
+            );
+        }
+        if let (Some(model), Some(config)) = (&bot.model, config) {
+            if !config.models.contains_key(model) {
+                report.error(
+                    line,
+                    format!("bots.{}.model: model '{}' is not configured", name, model),
+                );
+            }
+        }
+    }
+    for (alias, target) in &bots_config.aliases {
+        let line = report.line_of(&format!("{} =", alias));
+        if let Err(e) = bots::check_alias(alias, reserved) {
+            report.error(line, format!("aliases.{}: {}", alias, e));
+        }
+        if !bots_config.bots.contains_key(target) {
+            report.warning(line, format!("aliases.{}: bot '{}' not found", alias, target));
+        }
+    }
+    if let Some(current) = &bots_config.current {
+        if !bots_config.bots.contains_key(current) {
+            report.warning(
+                report.line_of("current"),
+                format!("current: bot '{}' not found", current),
+            );
+        }
+    }
+    report
+}
+
+fn check_agents(dir: &Path) -> Vec<FileReport> {
+    let mut reports = Vec::new();
+    let Ok(entries) = fs::read_dir(dir) else {
+        return reports;
+    };
+    let mut paths: Vec<PathBuf> = entries
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| path.extension().is_some_and(|ext| ext == "toml"))
+        .collect();
+    paths.sort();
+    for path in paths {
+        let mut report = FileReport::read(&path);
+        if let Some(agent) = report.parse::<Agent>(Schema::Agent) {
+            if agent.system_prompt.trim().is_empty() {
+                report.error(
+                    report.line_of("system_prompt"),
+                    "system_prompt: must not be empty".to_string(),
+                );
+            }
+        }
+        reports.push(report);
+    }
+    reports
+}
+
+fn check_registries(path: &Path) -> FileReport {
+    let mut report = FileReport::read(path);
+    match toml::from_str::<RegistryConfig>(&report.content) {
+        Ok(registries) => {
+            for (name, registry) in &registries.registries {
+                let line = report.line_of(&format!("[registries.{}]", name));
+                let mut single = RegistryConfig::default();
+                single.registries.insert(name.clone(), registry.clone());
+                if let Err(e) = single.load_bots().and_then(|_| single.load_agents()) {
+                    report.error(line, format!("registries.{}: {:#}", name, e));
+                }
+            }
+        }
+        Err(e) => {
+            let line = e.span().map(|span| report.line_of_offset(span.start));
+            report.error(line, e.message().to_string());
+        }
+    }
+    report
+}
+
+/// 检查所有配置文件并输出问题，发现错误时返回 Err
+pub fn run(reserved: &ReservedFlags) -> Result<()> {
+    let config_dir = utils::get_config_dir()
+        .ok_or_else(|| anyhow::anyhow!("can not find config directory"))?;
+    let mut reports = Vec::new();
+
+    let mut config = None;
+    if let Some(path) = Config::get_path().filter(|path| path.exists()) {
+        let (report, parsed) = check_config(&path);
+        config = parsed;
+        reports.push(report);
+    }
+    if let Some(path) = BotsConfig::get_path().filter(|path| path.exists()) {
+        reports.push(check_bots(&path, config.as_ref(), reserved));
+    }
+    if let Some(path) = RegistryConfig::get_path().filter(|path| path.exists()) {
+        reports.push(check_registries(&path));
+    }
+    if let Ok(agents_dir) = AgentManager::get_agents_dir() {
+        reports.extend(check_agents(&agents_dir));
+    }
+
+    if let Some(path) = utils::find_project_config() {
+        if path.is_dir() {
+            for (file, schema) in [("config.toml", Schema::Config), ("bots.toml", Schema::Bots)] {
+                let file_path = path.join(file);
+                if file_path.exists() {
+                    let mut report = FileReport::read(&file_path);
+                    report.parse::<ProjectConfig>(schema);
+                    reports.push(report);
+                }
+            }
+            reports.extend(check_agents(&path.join("agents")));
+        } else {
+            let mut report = FileReport::read(&path);
+            if let Err(e) = ProjectConfig::load_from(&path) {
+                report.error(None, format!("{:#}", e));
+            }
+            reports.push(report);
+        }
+    }
+
+    if reports.is_empty() {
+        println!("no config files found in {}", config_dir.display());
+        return Ok(());
+    }
+
+    let mut errors = 0;
+    let mut warnings = 0;
+    for report in &reports {
+        let (e, w) = report.print();
+        errors += e;
+        warnings += w;
+    }
+    println!();
+    println!("{} error(s), {} warning(s)", errors, warnings);
+    if errors > 0 {
+        return Err(anyhow::anyhow!("config doctor found {} error(s)", errors));
+    }
+    Ok(())
+}
@@ -1,10 +1,15 @@
-use anyhow::Result;
+use crate::cache::ResponseCache;
+use crate::http_log::HttpLog;
+use crate::schema::OutputSchema;
+use anyhow::{Context, Result};
 use futures::{Stream, StreamExt};
 use serde::{Deserialize, Serialize};
 use std::fmt;
+use std::path::PathBuf;
 use std::pin::Pin;
 use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
+use std::time::Duration;
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Message {
@@ -16,6 +21,17 @@ pub struct Message {
     pub function_call: Option<serde_json::Value>,
 }
 
+impl Message {
+    pub fn new(role: &str, content: String) -> Self {
+        Self {
+            role: role.to_string(),
+            content,
+            name: None,
+            function_call: None,
+        }
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct FunctionDef {
     pub name: String,
@@ -23,14 +39,520 @@ pub struct FunctionDef {
     pub parameters: serde_json::Value,
 }
 
+/// 生成参数
+/// 未设置的字段不会出现在请求体中，由服务端使用默认值
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct GenerationParams {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub temperature: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub top_p: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_tokens: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub stop: Option<Vec<String>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub presence_penalty: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub frequency_penalty: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub seed: Option<u64>,
+}
+
+impl GenerationParams {
+    /// 用 overrides 中已设置的字段覆盖当前参数
+    pub fn merge(&self, overrides: &GenerationParams) -> GenerationParams {
+        GenerationParams {
+            temperature: overrides.temperature.or(self.temperature),
+            top_p: overrides.top_p.or(self.top_p),
+            max_tokens: overrides.max_tokens.or(self.max_tokens),
+            stop: overrides.stop.clone().or_else(|| self.stop.clone()),
+            presence_penalty: overrides.presence_penalty.or(self.presence_penalty),
+            frequency_penalty: overrides.frequency_penalty.or(self.frequency_penalty),
+            seed: overrides.seed.or(self.seed),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        *self == GenerationParams::default()
+    }
+}
+
+impl fmt::Display for GenerationParams {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let value = serde_json::to_value(self).map_err(|_| fmt::Error)?;
+        let pairs: Vec<String> = value
+            .as_object()
+            .into_iter()
+            .flatten()
+            .map(|(key, value)| format!("{}={}", key, value))
+            .collect();
+        write!(f, "{}", pairs.join(", "))
+    }
+}
+
+/// 请求失败后的最大重试次数，只有限流、服务端错误和网络错误会重试
+const MAX_RETRIES: u32 = 2;
+
+/// 模型服务请求失败的原因
+/// 由 HTTP 状态码与服务端返回的错误内容解析得到，决定是否重试以及给用户的提示
+#[derive(Debug, Clone, PartialEq)]
+pub enum ProviderError {
+    /// API Key 无效、权限不足或额度用尽
+    Auth { status: Option<u16>, message: String },
+    /// 请求过于频繁
+    RateLimit { message: String, retry_after: Option<Duration> },
+    /// 上下文超出模型限制，tokens 为 (实际, 上限)
+    ContextLength { message: String, tokens: Option<(u64, u64)> },
+    /// 内容被服务端安全策略拦截
+    ContentFilter { message: String },
+    /// 请求参数、地址或模型名称有误
+    BadRequest { status: Option<u16>, message: String },
+    /// 服务端内部错误或过载
+    Server { status: Option<u16>, message: String },
+    /// 连接、TLS 或超时错误
+    Network { message: String },
+    /// 无法解析服务端的响应
+    Parse { message: String },
+}
+
+impl ProviderError {
+    /// 错误类型的简短名称
+    pub fn kind(&self) -> &'static str {
+        match self {
+            ProviderError::Auth { .. } => "auth",
+            ProviderError::RateLimit { .. } => "rate-limit",
+            ProviderError::ContextLength { .. } => "context-length",
+            ProviderError::ContentFilter { .. } => "content-filter",
+            ProviderError::BadRequest { .. } => "bad-request",
+            ProviderError::Server { .. } => "server",
+            ProviderError::Network { .. } => "network",
+            ProviderError::Parse { .. } => "parse",
+        }
+    }
+
+    pub fn message(&self) -> &str {
+        match self {
+            ProviderError::Auth { message, .. }
+            | ProviderError::RateLimit { message, .. }
+            | ProviderError::ContextLength { message, .. }
+            | ProviderError::ContentFilter { message }
+            | ProviderError::BadRequest { message, .. }
+            | ProviderError::Server { message, .. }
+            | ProviderError::Network { message }
+            | ProviderError::Parse { message } => message,
+        }
+    }
+
+    /// 服务端错误只重试临时性的状态码，501、505 等重试也不会成功
+    pub fn is_retryable(&self) -> bool {
+        matches!(
+            self,
+            ProviderError::RateLimit { .. }
+                | ProviderError::Server { status: Some(500 | 502 | 503 | 504 | 529), .. }
+                | ProviderError::Network { .. }
+        )
+    }
+
+    /// 第 attempt 次重试前的等待时间，优先使用服务端的 Retry-After
+    pub fn retry_delay(&self, attempt: u32) -> Duration {
+        let backoff = Duration::from_secs(1 << attempt.min(5));
+        match self {
+            ProviderError::RateLimit { retry_after: Some(delay), .. } => *delay,
+            _ => backoff,
+        }
+        .min(Duration::from_secs(30))
+    }
+
+    /// 按状态码与错误内容分类，兼容 OpenAI 格式 `{"error": {"message", "type", "code"}}`
+    /// 以及 `{"error": "..."}`、`{"message": "..."}`、`{"error_msg": "..."}` 等常见格式
+    pub fn from_body(status: Option<u16>, body: &str, retry_after: Option<Duration>) -> Self {
+        let json: serde_json::Value = serde_json::from_str(body).unwrap_or_default();
+        let error = if json["error"].is_object() { &json["error"] } else { &json };
+        let message = error["message"]
+            .as_str()
+            .or_else(|| json["error"].as_str())
+            .or_else(|| json["error_msg"].as_str())
+            .map(str::to_string)
+            .unwrap_or_else(|| truncate(body.trim(), 300));
+        let code = match &error["code"] {
+            serde_json::Value::String(code) => code.clone(),
+            serde_json::Value::Number(code) => code.to_string(),
+            _ => String::new(),
+        };
+        let error_type = error["type"].as_str().unwrap_or_default();
+        let lower = message.to_lowercase();
+        let tags = format!("{} {}", code, error_type).to_lowercase();
+
+        if tags.contains("context_length") || lower.contains("context length") || lower.contains("maximum context") || status == Some(413) {
+            let tokens = number_after(&lower, "resulted in ")
+                .or_else(|| number_after(&lower, "requested "))
+                .zip(number_after(&lower, "maximum context length is "));
+            return ProviderError::ContextLength { message, tokens };
+        }
+        if tags.contains("content_filter") || tags.contains("content_policy") || lower.contains("content management policy") {
+            return ProviderError::ContentFilter { message };
+        }
+        if tags.contains("insufficient_quota") || tags.contains("invalid_api_key") {
+            return ProviderError::Auth { status, message };
+        }
+        match status {
+            Some(401 | 403) => ProviderError::Auth { status, message },
+            Some(429) => ProviderError::RateLimit { message, retry_after },
+            Some(500..=599) => ProviderError::Server { status, message },
+            _ if tags.contains("rate_limit") => ProviderError::RateLimit { message, retry_after },
+            _ => ProviderError::BadRequest { status, message },
+        }
+    }
+
+    async fn from_response(response: reqwest::Response, log: Option<&mut HttpLog>) -> Self {
+        let status = response.status().as_u16();
+        let retry_after = response
+            .headers()
+            .get(reqwest::header::RETRY_AFTER)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.trim().parse().ok())
+            .map(Duration::from_secs);
+        match response.text().await {
+            Ok(body) => {
+                if let Some(log) = log {
+                    log.body(body.as_bytes());
+                }
+                Self::from_body(Some(status), &body, retry_after)
+            }
+            Err(e) => Self::from(e),
+        }
+    }
+}
+
+impl From<reqwest::Error> for ProviderError {
+    fn from(e: reqwest::Error) -> Self {
+        if e.is_decode() {
+            return ProviderError::Parse { message: e.to_string() };
+        }
+        if e.is_builder() {
+            return ProviderError::BadRequest {
+                status: None,
+                message: format!("invalid api_url: {}", root_cause(&e)),
+            };
+        }
+        ProviderError::Network { message: root_cause(&e) }
+    }
+}
+
+impl fmt::Display for ProviderError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let status = |status: &Option<u16>| status.map(|s| format!(" (HTTP {})", s)).unwrap_or_default();
+        match self {
+            ProviderError::Auth { status: code, message } => write!(
+                f,
+                "authentication failed{}: {}, check the API key with `gpt config model set <name> --key <key>`",
+                status(code),
+                message
+            ),
+            ProviderError::RateLimit { message, .. } => {
+                write!(f, "rate limited: {}, wait a moment and retry", message)
+            }
+            ProviderError::ContextLength { tokens: Some((used, limit)), .. } => write!(
+                f,
+                "context too long: {} > {} tokens, try /clear or a shorter input",
+                format_tokens(*used),
+                format_tokens(*limit)
+            ),
+            ProviderError::ContextLength { message, .. } => {
+                write!(f, "context too long: {}, try /clear or a shorter input", message)
+            }
+            ProviderError::ContentFilter { message } => {
+                write!(f, "blocked by the provider's content filter: {}, rephrase the request", message)
+            }
+            ProviderError::BadRequest { status: Some(404), message } => write!(
+                f,
+                "not found (HTTP 404): {}, check api_url and the model name",
+                message
+            ),
+            ProviderError::BadRequest { status: code, message } => {
+                write!(f, "bad request{}: {}", status(code), message)
+            }
+            ProviderError::Server { status: code, message } => write!(
+                f,
+                "provider error{}: {}, the service may be overloaded, retry later",
+                status(code),
+                message
+            ),
+            ProviderError::Network { message } => {
+                let lower = message.to_lowercase();
+                let hint = if lower.contains("certificate") || lower.contains("tls") || lower.contains("ssl") {
+                    "set ca_bundle, or insecure for this model, in config.toml"
+                } else if lower.contains("timed out") {
+                    "the provider did not respond in time, retry later"
+                } else {
+                    "check api_url, your network and proxy settings"
+                };
+                write!(f, "network error: {}, {}", message, hint)
+            }
+            ProviderError::Parse { message } => write!(f, "unexpected response: {}", message),
+        }
+    }
+}
+
+impl std::error::Error for ProviderError {}
+
+/// 最内层的错误信息，reqwest 的外层信息通常只有请求地址
+fn root_cause(e: &reqwest::Error) -> String {
+    let mut source: &dyn std::error::Error = e;
+    while let Some(inner) = source.source() {
+        source = inner;
+    }
+    source.to_string()
+}
+
+fn number_after(text: &str, pattern: &str) -> Option<u64> {
+    let start = text.find(pattern)? + pattern.len();
+    let digits: String = text[start..]
+        .chars()
+        .take_while(|c| c.is_ascii_digit() || *c == ',')
+        .filter(char::is_ascii_digit)
+        .collect();
+    digits.parse().ok()
+}
+
+/// 以 k 为单位显示 token 数，例如 8192 -> 8.2k
+fn format_tokens(tokens: u64) -> String {
+    if tokens < 1000 {
+        return tokens.to_string();
+    }
+    let value = format!("{:.1}", tokens as f64 / 1000.0);
+    format!("{}k", value.trim_end_matches(".0"))
+}
+
+fn truncate(text: &str, max_chars: usize) -> String {
+    match text.char_indices().nth(max_chars) {
+        Some((index, _)) => format!("{}...", &text[..index]),
+        None => text.to_string(),
+    }
+}
+
+/// HTTP 客户端的网络设置
+#[derive(Debug, Clone, Default)]
+pub struct HttpOptions {
+    /// 额外信任的 CA 证书文件（PEM），可以包含多个证书
+    pub ca_bundle: Option<PathBuf>,
+    /// 代理地址，未设置时使用 HTTPS_PROXY / HTTP_PROXY / ALL_PROXY 环境变量，
+    /// `none` 表示不使用代理
+    pub proxy: Option<String>,
+    /// 跳过 TLS 证书校验
+    pub insecure: bool,
+}
+
+impl HttpOptions {
+    /// 按设置构建 HTTP 客户端，证书校验默认开启
+    pub fn build_client(&self) -> Result<reqwest::Client> {
+        let mut builder = reqwest::Client::builder();
+        if let Some(path) = &self.ca_bundle {
+            let pem = std::fs::read(path)
+                .with_context(|| format!("failed to read ca_bundle {}", path.display()))?;
+            let certificates = reqwest::Certificate::from_pem_bundle(&pem)
+                .with_context(|| format!("invalid ca_bundle {}", path.display()))?;
+            for certificate in certificates {
+                builder = builder.add_root_certificate(certificate);
+            }
+        }
+        match self.proxy.as_deref().map(str::trim) {
+            Some("none") | Some("") => builder = builder.no_proxy(),
+            Some(url) => {
+                let proxy = reqwest::Proxy::all(url)
+                    .with_context(|| format!("invalid proxy {}", url))?
+                    .no_proxy(reqwest::NoProxy::from_env());
+                builder = builder.proxy(proxy);
+            }
+            // 由 reqwest 读取系统代理环境变量，包括 NO_PROXY
+            None => {}
+        }
+        if self.insecure {
+            builder = builder.danger_accept_invalid_certs(true);
+        }
+        Ok(builder.build()?)
+    }
+}
+
+/// 模型停止生成的原因
+#[derive(Debug, Clone, PartialEq)]
+pub enum FinishReason {
+    Stop,
+    /// 达到 max_tokens 或模型上限，回复被截断
+    Length,
+    ContentFilter,
+    ToolCalls,
+    Other(String),
+}
+
+impl FinishReason {
+    /// 解析响应中的 finish_reason，部分服务在未结束时会返回字符串 "null"
+    pub fn parse(value: &serde_json::Value) -> Option<Self> {
+        match value.as_str()? {
+            "" | "null" => None,
+            "stop" => Some(FinishReason::Stop),
+            "length" => Some(FinishReason::Length),
+            "content_filter" => Some(FinishReason::ContentFilter),
+            "tool_calls" | "function_call" => Some(FinishReason::ToolCalls),
+            other => Some(FinishReason::Other(other.to_string())),
+        }
+    }
+}
+
+impl fmt::Display for FinishReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FinishReason::Stop => write!(f, "stop"),
+            FinishReason::Length => write!(f, "length"),
+            FinishReason::ContentFilter => write!(f, "content_filter"),
+            FinishReason::ToolCalls => write!(f, "tool_calls"),
+            FinishReason::Other(reason) => write!(f, "{}", reason),
+        }
+    }
+}
+
+/// 流式返回的工具调用片段
+/// 同一次调用的片段 index 相同，id 和 name 通常只在第一个片段中出现，arguments 需要依次拼接
+#[derive(Debug, Clone, PartialEq)]
+pub struct ToolCallDelta {
+    pub index: usize,
+    pub id: Option<String>,
+    pub name: Option<String>,
+    pub arguments: String,
+}
+
+/// 本次请求的 token 用量
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Usage {
+    #[serde(default)]
+    pub prompt_tokens: u64,
+    #[serde(default)]
+    pub completion_tokens: u64,
+    #[serde(default)]
+    pub total_tokens: u64,
+}
+
+/// 回复流中的事件
+#[derive(Debug, Clone, PartialEq)]
+pub enum ChatEvent {
+    /// 回复正文
+    TextDelta(String),
+    /// 推理模型的思考过程（reasoning_content）
+    ReasoningDelta(String),
+    ToolCallDelta(ToolCallDelta),
+    Usage(Usage),
+    Finish(FinishReason),
+    /// 收到响应后出现的错误，之后不会再有事件
+    Error(ProviderError),
+}
+
+impl ChatEvent {
+    /// 从一个 choice 与所在的响应中解析事件，同时兼容流式的 delta 与非流式的 message
+    fn parse_all(response_json: &serde_json::Value) -> Vec<ChatEvent> {
+        let mut events = Vec::new();
+        let choice = &response_json["choices"][0];
+        let delta = if choice["delta"].is_object() { &choice["delta"] } else { &choice["message"] };
+
+        // DeepSeek 等使用 reasoning_content，OpenRouter 使用 reasoning
+        let reasoning = delta["reasoning_content"].as_str().or_else(|| delta["reasoning"].as_str());
+        if let Some(reasoning) = reasoning.filter(|reasoning| !reasoning.is_empty()) {
+            events.push(ChatEvent::ReasoningDelta(reasoning.to_string()));
+        }
+        if let Some(content) = delta["content"].as_str().filter(|content| !content.is_empty()) {
+            events.push(ChatEvent::TextDelta(content.to_string()));
+        }
+        if let Some(tool_calls) = delta["tool_calls"].as_array() {
+            for (i, tool_call) in tool_calls.iter().enumerate() {
+                events.push(ChatEvent::ToolCallDelta(ToolCallDelta {
+                    index: tool_call["index"].as_u64().map(|index| index as usize).unwrap_or(i),
+                    id: tool_call["id"].as_str().map(str::to_string),
+                    name: tool_call["function"]["name"].as_str().map(str::to_string),
+                    arguments: tool_call["function"]["arguments"].as_str().unwrap_or_default().to_string(),
+                }));
+            }
+        }
+        if let Some(reason) = FinishReason::parse(&choice["finish_reason"]) {
+            events.push(ChatEvent::Finish(reason));
+        }
+        if let Ok(usage) = serde_json::from_value::<Usage>(response_json["usage"].clone()) {
+            events.push(ChatEvent::Usage(usage));
+        }
+        events
+    }
+}
+
+pub type ChatStream = Pin<Box<dyn Stream<Item = ChatEvent> + Send>>;
+
+/// 只关心回复正文的调用方使用，错误事件转换为 Err，其他事件被忽略
+pub fn text_stream(events: ChatStream) -> Pin<Box<dyn Stream<Item = Result<String>> + Send>> {
+    Box::pin(events.filter_map(|event| async move {
+        match event {
+            ChatEvent::TextDelta(text) => Some(Ok(text)),
+            ChatEvent::Error(e) => Some(Err(e.into())),
+            _ => None,
+        }
+    }))
+}
+
+/// 按行切分 SSE 响应
+/// 网络分块可能在一行或一个 UTF-8 字符的中间断开，未结束的部分保留到下一个分块
+#[derive(Default)]
+struct SseLines {
+    buffer: Vec<u8>,
+}
+
+impl SseLines {
+    /// 追加一个分块，返回其中完整的行
+    fn push(&mut self, chunk: &[u8]) -> Vec<String> {
+        self.buffer.extend_from_slice(chunk);
+        let Some(end) = self.buffer.iter().rposition(|b| *b == b'\n') else {
+            return Vec::new();
+        };
+        let complete: Vec<u8> = self.buffer.drain(..=end).collect();
+        String::from_utf8_lossy(&complete)
+            .lines()
+            .map(str::to_string)
+            .collect()
+    }
+
+    /// 响应结束时返回最后一行没有换行符的内容
+    fn finish(&mut self) -> Option<String> {
+        if self.buffer.is_empty() {
+            return None;
+        }
+        let rest = std::mem::take(&mut self.buffer);
+        Some(String::from_utf8_lossy(&rest).into_owned())
+    }
+}
+
+/// 解析一行 SSE 数据
+fn parse_sse_line(line: &str) -> Vec<ChatEvent> {
+    let Some(data) = line.strip_prefix("data:") else {
+        return Vec::new();
+    };
+    let data = data.trim();
+    if data == "[DONE]" {
+        return Vec::new();
+    }
+    let Ok(response_json) = serde_json::from_str::<serde_json::Value>(data) else {
+        return Vec::new();
+    };
+    if !response_json["error"].is_null() || !response_json["error_msg"].is_null() {
+        return vec![ChatEvent::Error(ProviderError::from_body(None, data, None))];
+    }
+    ChatEvent::parse_all(&response_json)
+}
+
 #[async_trait::async_trait]
 pub trait LLMProvider: Send + Sync {
+    /// 发送请求，建立连接或服务端返回错误时直接返回 Err，之后的错误通过 ChatEvent::Error 传递
     async fn chat(
         &self,
         messages: Vec<Message>,
         stream: bool,
         running: Arc<AtomicBool>,
-    ) -> Result<Pin<Box<dyn Stream<Item = Result<String>> + Send>>>;
+    ) -> Result<ChatStream>;
 }
 
 pub struct Provider {
@@ -39,7 +561,12 @@ pub struct Provider {
     api_url: String,
     model: String,
     json_mode: bool,
+    output_schema: Option<OutputSchema>,
     functions: Option<Vec<FunctionDef>>,
+    params: GenerationParams,
+    cache: Option<ResponseCache>,
+    log_dir: Option<PathBuf>,
+    max_retries: u32,
 }
 
 impl fmt::Debug for Provider {
@@ -48,7 +575,12 @@ impl fmt::Debug for Provider {
             .field("api_url", &self.api_url)
             .field("model", &self.model)
             .field("json_mode", &self.json_mode)
+            .field("output_schema", &self.output_schema.as_ref().map(|schema| &schema.name))
             .field("functions", &self.functions)
+            .field("params", &self.params)
+            .field("cache", &self.cache.as_ref().map(ResponseCache::dir))
+            .field("log_dir", &self.log_dir)
+            .field("max_retries", &self.max_retries)
             // 不输出敏感信息
             .field("api_key", &"[REDACTED]")
             .finish()
@@ -63,7 +595,12 @@ impl Clone for Provider {
             api_url: self.api_url.clone(),
             model: self.model.clone(),
             json_mode: self.json_mode,
+            output_schema: self.output_schema.clone(),
             functions: self.functions.clone(),
+            params: self.params.clone(),
+            cache: self.cache.clone(),
+            log_dir: self.log_dir.clone(),
+            max_retries: self.max_retries,
         }
     }
 }
@@ -71,19 +608,26 @@ impl Clone for Provider {
 impl Provider {
     pub fn new(api_key: String) -> Self {
         Self {
-            client: reqwest::Client::builder()
-                .danger_accept_invalid_certs(true)
-                .no_proxy()
-                .build()
-                .unwrap_or_else(|_| reqwest::Client::new()),
+            client: reqwest::Client::new(),
             api_key,
             api_url: "https://api.openai.com/v1/chat/completions".to_string(),
             model: "gpt-3.5-turbo".to_string(),
             json_mode: false,
+            output_schema: None,
             functions: None,
+            params: GenerationParams::default(),
+            cache: None,
+            log_dir: None,
+            max_retries: MAX_RETRIES,
         }
     }
 
+    /// 使用指定的证书、代理设置重新构建 HTTP 客户端
+    pub fn with_http_options(mut self, options: &HttpOptions) -> Result<Self> {
+        self.client = options.build_client()?;
+        Ok(self)
+    }
+
     pub fn with_url(mut self, url: String) -> Self {
         self.api_url = url;
         self
@@ -99,6 +643,40 @@ impl Provider {
         self
     }
 
+    /// 要求按 JSON Schema 输出，优先于 json_mode
+    pub fn with_output_schema(mut self, schema: Option<OutputSchema>) -> Self {
+        self.output_schema = schema;
+        self
+    }
+
+    pub fn with_functions(mut self, functions: Vec<FunctionDef>) -> Self {
+        self.functions = Some(functions);
+        self
+    }
+
+    pub fn with_params(mut self, params: GenerationParams) -> Self {
+        self.params = params;
+        self
+    }
+
+    /// 缓存完整的回复，相同的请求直接重放缓存
+    pub fn with_cache(mut self, cache: Option<ResponseCache>) -> Self {
+        self.cache = cache;
+        self
+    }
+
+    /// 把每次请求的原始内容与响应记录到该目录，用于排查服务商的问题
+    pub fn with_log_dir(mut self, dir: Option<PathBuf>) -> Self {
+        self.log_dir = dir;
+        self
+    }
+
+    /// 限流、服务端或网络错误时的重试次数，由调用方自己重试时设为 0
+    pub fn with_max_retries(mut self, retries: u32) -> Self {
+        self.max_retries = retries;
+        self
+    }
+
     fn is_v36(&self) -> bool {
         self.api_url.contains("free.v36.cm")
     }
@@ -121,15 +699,27 @@ impl LLMProvider for Provider {
         messages: Vec<Message>,
         stream: bool,
         running: Arc<AtomicBool>,
-    ) -> Result<Pin<Box<dyn Stream<Item = Result<String>> + Send>>> {
-        let request_body = {
+    ) -> Result<ChatStream> {
+        let mut request_body = {
             let mut body = serde_json::json!({
                 "model": self.model,
                 "messages": messages,
                 "stream": stream
             });
+            // 流式响应默认不返回 token 用量，不支持该字段的服务会在第一次请求失败后去掉重试
+            if stream {
+                body["stream_options"] = serde_json::json!({"include_usage": true});
+            }
 
-            if self.json_mode {
+            if let Some(params) = serde_json::to_value(&self.params)?.as_object() {
+                body.as_object_mut().unwrap().extend(params.clone());
+            }
+
+            if let Some(schema) = &self.output_schema {
+                body.as_object_mut()
+                    .unwrap()
+                    .insert("response_format".to_string(), schema.response_format());
+            } else if self.json_mode {
                 body.as_object_mut().unwrap().insert(
                     "response_format".to_string(),
                     serde_json::json!({"type": "json_object"}),
@@ -137,9 +727,13 @@ impl LLMProvider for Provider {
             }
 
             if let Some(functions) = &self.functions {
+                let tools: Vec<serde_json::Value> = functions
+                    .iter()
+                    .map(|function| serde_json::json!({"type": "function", "function": function}))
+                    .collect();
                 body.as_object_mut()
                     .unwrap()
-                    .insert("functions".to_string(), serde_json::json!(functions));
+                    .insert("tools".to_string(), serde_json::json!(tools));
             }
             body
         };
@@ -168,75 +762,286 @@ impl LLMProvider for Provider {
 
         let api_url = self.get_api_url();
 
-        let response = self
-            .client
-            .post(&api_url)
-            .headers(headers)
-            .json(&request_body)
-            .send()
-            .await?;
-
-        let status = response.status();
-        if !status.is_success() {
-            let error_text = response.text().await?;
-            return Err(anyhow::anyhow!(
-                "API request failed with status {}: {}",
-                status,
-                error_text
-            ));
+        let cache = self
+            .cache
+            .as_ref()
+            .filter(|cache| cache.applies_to(self.params.temperature))
+            .map(|cache| (cache, ResponseCache::key(&api_url, &request_body)));
+        if let Some(events) = cache.as_ref().and_then(|(cache, key)| cache.get(key)) {
+            return Ok(events);
         }
+        let record = |events: ChatStream| match &cache {
+            Some((cache, key)) => cache.record(key.clone(), events),
+            None => events,
+        };
 
-        let running = running.clone();
-        if !stream {
-            let response_text = response.text().await?;
-            let response_json: serde_json::Value = serde_json::from_str(&response_text)?;
-
-            let content =  response_json["choices"][0]["message"]["content"].as_str()
-            .ok_or_else(|| anyhow::anyhow!("Failed to extract content from response"))?
-            .to_string();
+        let mut attempt = 0;
+        let (response, mut log) = loop {
+            let mut log = self
+                .log_dir
+                .as_ref()
+                .and_then(|dir| HttpLog::create(dir, &self.model, &api_url, &headers, &request_body));
+            let error = match self
+                .client
+                .post(&api_url)
+                .headers(headers.clone())
+                .json(&request_body)
+                .send()
+                .await
+            {
+                Ok(response) => {
+                    if let Some(log) = log.as_mut() {
+                        log.response(&response);
+                    }
+                    if response.status().is_success() {
+                        break (response, log);
+                    }
+                    ProviderError::from_response(response, log.as_mut()).await
+                }
+                Err(e) => {
+                    let error = ProviderError::from(e);
+                    if let Some(log) = log.as_mut() {
+                        log.error(&error);
+                    }
+                    error
+                }
+            };
+            // 部分兼容服务拒绝未知字段，去掉 stream_options 后立即重试，不计入重试次数
+            if let ProviderError::BadRequest { message, .. } = &error {
+                if message.contains("stream_options")
+                    && request_body.as_object_mut().is_some_and(|body| body.remove("stream_options").is_some())
+                {
+                    continue;
+                }
+            }
+            if attempt >= self.max_retries || !error.is_retryable() || !running.load(Ordering::SeqCst) {
+                return Err(error.into());
+            }
+            if !sleep_unless_cancelled(error.retry_delay(attempt), &running).await {
+                return Err(error.into());
+            }
+            attempt += 1;
+        };
 
-            let stream = futures::stream::once(async { Ok(content) });
-            return Ok(Box::pin(stream));
+        let running = running.clone();
+        // 部分服务在出错或不支持流式输出时会直接返回 JSON
+        let is_json = is_json_content(
+            response
+                .headers()
+                .get(reqwest::header::CONTENT_TYPE)
+                .and_then(|value| value.to_str().ok()),
+        );
+        if !stream || is_json {
+            let response_text = response.text().await.map_err(ProviderError::from)?;
+            if let Some(log) = log.as_mut() {
+                log.body(response_text.as_bytes());
+            }
+            let events = parse_json_response(&response_text)?;
+            return Ok(record(Box::pin(futures::stream::iter(events))));
         }
 
-        let stream = response
+        let bytes = response
             .bytes_stream()
             .take_while(move |_| {
                 let continue_running = running.load(Ordering::SeqCst);
                 async move { continue_running }
             })
-            .map(move |chunk| -> Result<String> {
-                let chunk = chunk.map_err(anyhow::Error::from)?;
-                let text = String::from_utf8_lossy(&chunk);
-
-                let mut result = String::new();
-                for line in text.lines() {
-                    if line.starts_with("data: ") {
-                        let json_str = &line[6..];
-                        if json_str.trim() == "[DONE]" {
-                            continue;
-                        }
-                        if let Ok(response_json) =
-                            serde_json::from_str::<serde_json::Value>(json_str)
-                        {
-                            if let Some(error_msg) = response_json["error_msg"].as_str() {
-                                return Err(anyhow::anyhow!("API Error: {}", error_msg));
-                            }
-                            if let Some(content) =
-                                response_json["choices"][0]["delta"]["content"].as_str()
-                            {
-                                result.push_str(content);
-                            } else if let Some(content) =
-                                response_json["choices"][0]["message"]["content"].as_str()
-                            {
-                                result.push_str(content);
-                            }
-                        }
-                    }
+            .map(move |chunk| {
+                if let (Some(log), Ok(chunk)) = (log.as_mut(), &chunk) {
+                    log.body(chunk);
                 }
-                Ok(result)
+                chunk.map_err(ProviderError::from)
             });
+        Ok(record(sse_events(bytes)))
+    }
+}
+
+/// 等待指定时间，期间被 Ctrl+C 中断时提前返回 false
+pub async fn sleep_unless_cancelled(delay: Duration, running: &AtomicBool) -> bool {
+    tokio::select! {
+        _ = tokio::time::sleep(delay) => running.load(Ordering::SeqCst),
+        _ = async {
+            while running.load(Ordering::SeqCst) {
+                tokio::time::sleep(Duration::from_millis(50)).await;
+            }
+        } => false,
+    }
+}
+
+fn is_json_content(content_type: Option<&str>) -> bool {
+    content_type.is_some_and(|value| value.starts_with("application/json"))
+}
+
+/// 解析非流式的 JSON 响应
+fn parse_json_response(response_text: &str) -> Result<Vec<ChatEvent>, ProviderError> {
+    let response_json: serde_json::Value = serde_json::from_str(response_text)
+        .map_err(|e| ProviderError::Parse { message: format!("invalid JSON: {}", e) })?;
+    if !response_json["error"].is_null() || !response_json["error_msg"].is_null() {
+        return Err(ProviderError::from_body(None, response_text, None));
+    }
+
+    let message = &response_json["choices"][0]["message"];
+    if !message.is_object() {
+        return Err(ProviderError::Parse {
+            message: format!("no message in response: {}", truncate(response_text, 300)),
+        });
+    }
+    Ok(ChatEvent::parse_all(&response_json))
+}
+
+/// 把 SSE 响应的字节流转换为事件流
+fn sse_events<S>(bytes: S) -> ChatStream
+where
+    S: Stream<Item = Result<bytes::Bytes, ProviderError>> + Send + 'static,
+{
+    let stream = futures::stream::unfold(
+        (Box::pin(bytes), SseLines::default(), false),
+        |(mut bytes, mut lines, done)| async move {
+            if done {
+                return None;
+            }
+            let (events, done) = match bytes.next().await {
+                Some(Ok(chunk)) => {
+                    let events: Vec<ChatEvent> = lines.push(&chunk).iter().flat_map(|line| parse_sse_line(line)).collect();
+                    // 服务端在流中返回错误后不再继续读取
+                    let failed = events.iter().any(|event| matches!(event, ChatEvent::Error(_)));
+                    (events, failed)
+                }
+                Some(Err(e)) => (vec![ChatEvent::Error(e)], true),
+                None => (lines.finish().map(|line| parse_sse_line(&line)).unwrap_or_default(), true),
+            };
+            Some((futures::stream::iter(events), (bytes, lines, done)))
+        },
+    )
+    .flatten();
+    Box::pin(stream)
+}
+
+/// 重放 HTTP 日志时每个分块的字节数
+const REPLAY_CHUNK_SIZE: usize = 7;
+
+/// 按请求时相同的逻辑解析记录下来的响应，用于离线重放 HTTP 日志
+pub fn parse_recorded(status: u16, content_type: Option<&str>, body: Vec<u8>) -> Result<ChatStream> {
+    if !(200..300).contains(&status) {
+        let body = String::from_utf8_lossy(&body);
+        return Err(ProviderError::from_body(Some(status), &body, None).into());
+    }
+    if is_json_content(content_type) {
+        let events = parse_json_response(&String::from_utf8_lossy(&body))?;
+        return Ok(Box::pin(futures::stream::iter(events)));
+    }
+    // 日志不保留网络分块的边界，按小块重放，使行或字符在分块中间断开的情况同样会出现
+    let chunks: Vec<Result<bytes::Bytes, ProviderError>> = body
+        .chunks(REPLAY_CHUNK_SIZE)
+        .map(|chunk| Ok(bytes::Bytes::copy_from_slice(chunk)))
+        .collect();
+    Ok(sse_events(futures::stream::iter(chunks)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 把 SSE 响应按每 size 字节切成分块解析，返回正文
+    async fn collect_text(body: &str, size: usize) -> String {
+        let chunks: Vec<Result<bytes::Bytes, ProviderError>> = body
+            .as_bytes()
+            .chunks(size)
+            .map(|chunk| Ok(bytes::Bytes::copy_from_slice(chunk)))
+            .collect();
+        let mut text = text_stream(sse_events(futures::stream::iter(chunks)));
+        let mut reply = String::new();
+        while let Some(chunk) = text.next().await {
+            reply.push_str(&chunk.unwrap());
+        }
+        reply
+    }
+
+    #[test]
+    fn sse_lines_keep_partial_lines_for_the_next_chunk() {
+        let mut lines = SseLines::default();
+        assert!(lines.push(b"data: {\"a\"").is_empty());
+        assert_eq!(lines.push(b": 1}\r\n\ndata: [DO"), ["data: {\"a\": 1}", ""]);
+        assert_eq!(lines.push(b"NE]\n"), ["data: [DONE]"]);
+        assert_eq!(lines.finish(), None);
+    }
+
+    #[test]
+    fn sse_lines_join_utf8_characters_split_across_chunks() {
+        let line = "data: 你好，世界\n".as_bytes();
+        let mut lines = SseLines::default();
+        let mut output = Vec::new();
+        for byte in line {
+            output.extend(lines.push(std::slice::from_ref(byte)));
+        }
+        assert_eq!(output, ["data: 你好，世界"]);
+    }
 
-        Ok(Box::pin(stream))
+    #[test]
+    fn sse_lines_return_the_unterminated_last_line() {
+        let mut lines = SseLines::default();
+        assert!(lines.push(b"data: [DONE]").is_empty());
+        assert_eq!(lines.finish().as_deref(), Some("data: [DONE]"));
+    }
+
+    #[tokio::test]
+    async fn sse_events_parse_any_chunking() {
+        let body = "data: {\"choices\":[{\"delta\":{\"content\":\"你好\"}}]}\n\n\
+                    data: {\"choices\":[{\"delta\":{\"content\":\"，世界\"},\"finish_reason\":\"stop\"}]}\n\n\
+                    data: [DONE]";
+        for size in [1, 2, 3, 7, body.len()] {
+            assert_eq!(collect_text(body, size).await, "你好，世界", "chunk size {}", size);
+        }
+    }
+
+    #[test]
+    fn from_body_reads_openai_errors() {
+        let body = r#"{"error": {"message": "Incorrect API key provided", "type": "invalid_request_error", "code": "invalid_api_key"}}"#;
+        let error = ProviderError::from_body(Some(400), body, None);
+        assert_eq!(
+            error,
+            ProviderError::Auth { status: Some(400), message: "Incorrect API key provided".into() }
+        );
+    }
+
+    #[test]
+    fn from_body_reads_other_error_formats() {
+        let error = ProviderError::from_body(Some(400), r#"{"error": "model not found"}"#, None);
+        assert_eq!(error, ProviderError::BadRequest { status: Some(400), message: "model not found".into() });
+        let error = ProviderError::from_body(Some(403), r#"{"message": "forbidden"}"#, None);
+        assert_eq!(error, ProviderError::Auth { status: Some(403), message: "forbidden".into() });
+        let error = ProviderError::from_body(Some(500), r#"{"error_msg": "internal"}"#, None);
+        assert_eq!(error, ProviderError::Server { status: Some(500), message: "internal".into() });
+        let error = ProviderError::from_body(Some(502), "<html>Bad Gateway</html>", None);
+        assert_eq!(error.message(), "<html>Bad Gateway</html>");
+    }
+
+    #[test]
+    fn from_body_detects_context_length() {
+        let body = r#"{"error": {"message": "This model's maximum context length is 8192 tokens. However, your messages resulted in 9000 tokens.", "code": "context_length_exceeded"}}"#;
+        match ProviderError::from_body(Some(400), body, None) {
+            ProviderError::ContextLength { tokens, .. } => assert_eq!(tokens, Some((9000, 8192))),
+            other => panic!("unexpected error: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn from_body_keeps_retry_after_for_rate_limits() {
+        let delay = Some(Duration::from_secs(7));
+        let error = ProviderError::from_body(Some(429), "{}", delay);
+        assert!(error.is_retryable());
+        assert_eq!(error.retry_delay(0), Duration::from_secs(7));
+        let error = ProviderError::from_body(Some(400), r#"{"error": {"message": "slow down", "type": "rate_limit_error"}}"#, None);
+        assert!(matches!(error, ProviderError::RateLimit { .. }));
+    }
+
+    #[test]
+    fn only_transient_server_errors_are_retried() {
+        for status in [500, 502, 503, 504, 529] {
+            assert!(ProviderError::from_body(Some(status), "", None).is_retryable(), "{}", status);
+        }
+        for status in [400, 401, 404, 501, 505] {
+            assert!(!ProviderError::from_body(Some(status), "", None).is_retryable(), "{}", status);
+        }
     }
 }
@@ -1,5 +1,5 @@
-/// 主模块
-/// 包含命令行界面、交互式聊天、配置管理等核心功能
+//! 主模块
+//! 包含命令行界面、交互式聊天、配置管理等核心功能
 
 mod llm_provider;
 mod config;
@@ -7,6 +7,19 @@ mod bots;
 mod update;
 mod utils;
 mod agents;
+mod batch;
+mod cache;
+mod compare;
+mod doctor;
+mod http_log;
+mod migrate;
+mod probe;
+mod project;
+mod registry;
+mod schema;
+mod serve;
+mod shell;
+mod template;
 
 use clap::{Command, Arg};
 use colored::*;
@@ -14,30 +27,73 @@ use dotenv::dotenv;
 use anyhow::Result;
 use futures::StreamExt;
 use std::io::{self, Write, BufRead};
-use llm_provider::{LLMProvider, Message, Provider};
+use llm_provider::{ChatEvent, FinishReason, GenerationParams, LLMProvider, Message, Provider, ProviderError};
 use config::Config;
-use bots::BotsConfig;
+use bots::{Bot, BotsConfig, ConflictStrategy, OutputFormat, ReservedFlags};
 use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
-use ctrlc;
 use std::time::Duration;
 use tokio::time::sleep;
 use tokio::select;
 use update::Update;
 use std::collections::HashMap;
 use std::fs;
+use std::path::PathBuf;
 use agents::{Agent, AgentManager};
+use template::TemplateContext;
+use registry::RegistryConfig;
+use project::TrustedProjects;
+use schema::OutputSchema;
 
 const CURRENT_VERSION: &str = env!("CARGO_PKG_VERSION");
 
-/// 添加辅助函数
-fn create_message(role: &str, content: String) -> Message {
-    Message {
-        role: role.to_string(),
-        content,
-        name: None,
-        function_call: None,
-    }
+/// 回复因长度截断后请求续写的提示
+const CONTINUE_PROMPT: &str = "Continue exactly where you stopped. Do not repeat anything you already wrote.";
+
+/// 结构化输出校验失败后重新请求的最大次数
+const SCHEMA_RETRIES: u32 = 2;
+
+/// 生成参数相关的命令行参数，供对话和 `config model set` 共用
+fn generation_args() -> Vec<Arg> {
+    vec![
+        Arg::new("temperature")
+            .long("temperature")
+            .help("sampling temperature")
+            .value_name("FLOAT")
+            .value_parser(clap::value_parser!(f64)),
+        Arg::new("top_p")
+            .long("top-p")
+            .help("nucleus sampling probability")
+            .value_name("FLOAT")
+            .value_parser(clap::value_parser!(f64)),
+        Arg::new("max_tokens")
+            .long("max-tokens")
+            .help("maximum number of tokens to generate")
+            .value_name("N")
+            .value_parser(clap::value_parser!(u32)),
+        Arg::new("stop")
+            .long("stop")
+            .help("stop sequence, can be repeated")
+            .value_name("TEXT")
+            .action(clap::ArgAction::Append),
+        Arg::new("presence_penalty")
+            .long("presence-penalty")
+            .help("presence penalty")
+            .value_name("FLOAT")
+            .value_parser(clap::value_parser!(f64))
+            .allow_negative_numbers(true),
+        Arg::new("frequency_penalty")
+            .long("frequency-penalty")
+            .help("frequency penalty")
+            .value_name("FLOAT")
+            .value_parser(clap::value_parser!(f64))
+            .allow_negative_numbers(true),
+        Arg::new("seed")
+            .long("seed")
+            .help("random seed for deterministic sampling")
+            .value_name("N")
+            .value_parser(clap::value_parser!(u64)),
+    ]
 }
 
 /// 构建命令行界面
@@ -60,6 +116,47 @@ fn build_cli() -> Command {
                 .help("use specified agent")
                 .value_name("AGENT")
         )
+        .args(generation_args())
+        .arg(
+            Arg::new("auto_continue")
+                .long("auto-continue")
+                .help("continue truncated answers up to N times, overrides auto_continue in config")
+                .value_name("N")
+                .value_parser(clap::value_parser!(u32))
+        )
+        .arg(
+            Arg::new("schema")
+                .long("schema")
+                .help("require the answer to match a JSON Schema file, only valid JSON is printed")
+                .value_name("FILE")
+                .value_parser(clap::value_parser!(PathBuf))
+        )
+        .arg(
+            Arg::new("hide_reasoning")
+                .long("hide-reasoning")
+                .help("do not show the thinking process of reasoning models")
+                .action(clap::ArgAction::SetTrue)
+        )
+        .arg(
+            Arg::new("no_cache")
+                .long("no-cache")
+                .help("do not read or write the response cache")
+                .action(clap::ArgAction::SetTrue)
+        )
+        .arg(
+            Arg::new("debug_http")
+                .long("debug-http")
+                .help("record raw requests and responses to log_dir, or ~/.gpt-shell/logs")
+                .action(clap::ArgAction::SetTrue)
+        )
+        .arg(
+            Arg::new("var")
+                .long("var")
+                .help("template variable for bot prompts, can be repeated")
+                .value_name("KEY=VALUE")
+                .value_parser(template::parse_var)
+                .action(clap::ArgAction::Append)
+        )
         .arg(
             Arg::new("prompt")
                 .help("prompt text to send to GPT")
@@ -81,9 +178,9 @@ fn build_cli() -> Command {
                     .about("model management")
                     .subcommand(
                         Command::new("add")
-                            .about("add new model")
+                            .about("add new model, URL and model default to the provider preset")
                             .arg(Arg::new("name").required(true))
-                            .arg(Arg::new("key").required(true))
+                            .arg(Arg::new("key").help("API key, optional for local providers"))
                             .arg(
                                 Arg::new("url")
                                     .long("url")
@@ -94,6 +191,57 @@ fn build_cli() -> Command {
                                     .long("model")
                                     .help("model name")
                             )
+                            .arg(
+                                Arg::new("provider")
+                                    .long("provider")
+                                    .help("provider preset, defaults to the model name if it matches one")
+                                    .value_parser(config::PROVIDER_PRESETS.iter().map(|p| p.name).collect::<Vec<_>>())
+                            )
+                    )
+                    .subcommand(
+                        Command::new("set")
+                            .about("change fields of an existing model")
+                            .arg(Arg::new("name").required(true))
+                            .arg(
+                                Arg::new("key")
+                                    .long("key")
+                                    .help("API key")
+                            )
+                            .arg(
+                                Arg::new("url")
+                                    .long("url")
+                                    .help("API URL")
+                            )
+                            .arg(
+                                Arg::new("model")
+                                    .long("model")
+                                    .help("model name")
+                            )
+                            .args(generation_args())
+                    )
+                    .subcommand(
+                        Command::new("test")
+                            .about("check connectivity, credentials and supported features")
+                            .arg(Arg::new("name").help("model to test, defaults to the current model"))
+                            .arg(
+                                Arg::new("all")
+                                    .long("all")
+                                    .help("test all configured models")
+                                    .action(clap::ArgAction::SetTrue)
+                                    .conflicts_with("name")
+                            )
+                    )
+                    .subcommand(
+                        Command::new("rename")
+                            .about("rename model")
+                            .arg(Arg::new("name").required(true))
+                            .arg(Arg::new("new_name").required(true))
+                    )
+                    .subcommand(
+                        Command::new("copy")
+                            .about("copy model to a new name")
+                            .arg(Arg::new("name").required(true))
+                            .arg(Arg::new("new_name").required(true))
                     )
                     .subcommand(
                         Command::new("remove")
@@ -123,11 +271,37 @@ fn build_cli() -> Command {
             .subcommand(
                 Command::new("show")
                     .about("show current configuration")
+                    .arg(
+                        Arg::new("format")
+                            .long("format")
+                            .value_parser(["toml", "json"])
+                            .help("print the whole config file in the given format, API keys are redacted")
+                    )
+            )
+            .subcommand(
+                Command::new("get")
+                    .about("get a config value by dotted path, e.g. models.openai.model")
+                    .arg(Arg::new("key").required(true))
+            )
+            .subcommand(
+                Command::new("set")
+                    .about("set a config value by dotted path")
+                    .arg(Arg::new("key").required(true))
+                    .arg(Arg::new("value").required(true).allow_hyphen_values(true))
+            )
+            .subcommand(
+                Command::new("unset")
+                    .about("remove a config value by dotted path")
+                    .arg(Arg::new("key").required(true))
             )
             .subcommand(
                 Command::new("edit")
                     .about("edit configuration file")
             )
+            .subcommand(
+                Command::new("doctor")
+                    .about("validate all configuration files")
+            )
     );
 
     cmd = cmd.subcommand(
@@ -143,6 +317,24 @@ fn build_cli() -> Command {
                             .long("system")
                             .required(true)
                     )
+                    .arg(
+                        Arg::new("model")
+                            .long("model")
+                            .help("pin the bot to a configured model")
+                    )
+                    .arg(
+                        Arg::new("format")
+                            .long("format")
+                            .help("output format: text or json")
+                            .value_parser(["text", "json"])
+                    )
+                    .arg(
+                        Arg::new("schema")
+                            .long("schema")
+                            .help("JSON Schema file the answers must match, stored in the bot")
+                            .value_name("FILE")
+                            .value_parser(clap::value_parser!(PathBuf))
+                    )
             )
             .subcommand(
                 Command::new("remove")
@@ -166,6 +358,61 @@ fn build_cli() -> Command {
                 Command::new("clear")
                     .about("clear current bot")
             )
+            .subcommand(
+                Command::new("export")
+                    .about("export bot with its aliases and examples as a bundle")
+                    .arg(Arg::new("name").required(true))
+                    .arg(
+                        Arg::new("output")
+                            .short('o')
+                            .long("output")
+                            .help("output file, prints to stdout if omitted")
+                            .value_parser(clap::value_parser!(PathBuf))
+                    )
+            )
+            .subcommand(
+                Command::new("import")
+                    .about("import bot bundles from a file or directory")
+                    .arg(
+                        Arg::new("path")
+                            .required(true)
+                            .value_parser(clap::value_parser!(PathBuf))
+                    )
+                    .arg(
+                        Arg::new("on_conflict")
+                            .long("on-conflict")
+                            .help("how to handle existing bots")
+                            .value_parser(["skip", "overwrite", "rename"])
+                            .default_value("skip")
+                    )
+            )
+            .subcommand(
+                Command::new("example")
+                    .about("few-shot example management")
+                    .subcommand(
+                        Command::new("add")
+                            .about("add an example conversation to bot")
+                            .arg(Arg::new("bot").required(true))
+                            .arg(Arg::new("user").required(true).help("example user input"))
+                            .arg(Arg::new("assistant").required(true).help("expected assistant reply"))
+                    )
+                    .subcommand(
+                        Command::new("list")
+                            .about("list examples of bot")
+                            .arg(Arg::new("bot").required(true))
+                    )
+                    .subcommand(
+                        Command::new("remove")
+                            .about("remove example from bot")
+                            .arg(Arg::new("bot").required(true))
+                            .arg(
+                                Arg::new("index")
+                                    .required(true)
+                                    .help("example number shown by list")
+                                    .value_parser(clap::value_parser!(usize))
+                            )
+                    )
+            )
             .subcommand(
                 Command::new("alias")
                     .about("alias management")
@@ -187,6 +434,245 @@ fn build_cli() -> Command {
             )
     );
 
+    cmd = cmd.subcommand(
+        Command::new("registry")
+            .about("shared bot and agent registries")
+            .subcommand(
+                Command::new("add")
+                    .about("add registry from a local directory or git url")
+                    .arg(Arg::new("source").required(true))
+                    .arg(
+                        Arg::new("name")
+                            .long("name")
+                            .help("namespace of the registry, defaults to the source name")
+                    )
+                    .arg(
+                        Arg::new("pin")
+                            .long("pin")
+                            .help("git tag, branch or commit to pin")
+                    )
+            )
+            .subcommand(
+                Command::new("remove")
+                    .about("remove registry")
+                    .arg(Arg::new("name").required(true))
+            )
+            .subcommand(
+                Command::new("list")
+                    .about("list all registries")
+            )
+            .subcommand(
+                Command::new("update")
+                    .about("sync registries from their sources")
+                    .arg(Arg::new("name").help("registry to update, updates all if omitted"))
+            )
+            .subcommand(
+                Command::new("pin")
+                    .about("pin registry to a git revision, unpin if revision is omitted")
+                    .arg(Arg::new("name").required(true))
+                    .arg(Arg::new("revision"))
+            )
+    );
+
+    cmd = cmd.subcommand(
+        Command::new("project")
+            .about("show or change whether the project config of the current directory is trusted")
+            .subcommand(
+                Command::new("trust")
+                    .about("trust the project config found from the current directory")
+            )
+            .subcommand(
+                Command::new("untrust")
+                    .about("stop loading the project config found from the current directory")
+            )
+    );
+
+    cmd = cmd.subcommand(
+        Command::new("compare")
+            .about("ask several models the same question and compare the answers")
+            .arg(Arg::new("prompt").required(true))
+            .arg(
+                Arg::new("model")
+                    .short('m')
+                    .long("model")
+                    .help("model to compare, can be repeated")
+                    .value_name("MODEL")
+                    .action(clap::ArgAction::Append)
+                    .required(true)
+            )
+            .arg(
+                Arg::new("judge")
+                    .long("judge")
+                    .help("model that scores the answers")
+                    .value_name("MODEL")
+            )
+            .arg(
+                Arg::new("columns")
+                    .long("columns")
+                    .help("show the answers side by side after all models finish")
+                    .action(clap::ArgAction::SetTrue)
+            )
+            .args(generation_args())
+    );
+
+    cmd = cmd.subcommand(
+        Command::new("batch")
+            .about("run many prompts from a JSONL or CSV file, resumable after interruption")
+            .arg(
+                Arg::new("input")
+                    .short('i')
+                    .long("input")
+                    .help("JSONL or CSV file, one prompt per line or row")
+                    .value_name("FILE")
+                    .value_parser(clap::value_parser!(PathBuf))
+                    .required(true)
+            )
+            .arg(
+                Arg::new("output")
+                    .short('o')
+                    .long("output")
+                    .help("JSONL file the results are appended to")
+                    .value_name("FILE")
+                    .value_parser(clap::value_parser!(PathBuf))
+                    .required(true)
+            )
+            .arg(
+                Arg::new("format")
+                    .long("format")
+                    .help("input format, detected from the file extension by default")
+                    .value_parser(["jsonl", "csv"])
+            )
+            .arg(
+                Arg::new("template")
+                    .short('t')
+                    .long("template")
+                    .help("prompt template using input columns, e.g. \"Summarize: {{text}}\"")
+                    .value_name("TEMPLATE")
+            )
+            .arg(
+                Arg::new("model")
+                    .short('m')
+                    .long("model")
+                    .help("model to use, defaults to the current model")
+            )
+            .arg(
+                Arg::new("bot")
+                    .long("bot")
+                    .help("bot whose system prompt and settings are used")
+            )
+            .arg(
+                Arg::new("concurrency")
+                    .short('j')
+                    .long("concurrency")
+                    .help("number of requests in flight")
+                    .value_name("N")
+                    .default_value("4")
+                    .value_parser(clap::value_parser!(usize))
+            )
+            .arg(
+                Arg::new("retries")
+                    .long("retries")
+                    .help("extra attempts per prompt on rate limit, server or network errors")
+                    .value_name("N")
+                    .default_value("3")
+                    .value_parser(clap::value_parser!(u32))
+            )
+            .args(generation_args())
+    );
+
+    cmd = cmd.subcommand(
+        Command::new("serve")
+            .about("serve an OpenAI-compatible API backed by the configured models and bots")
+            .arg(
+                Arg::new("port")
+                    .short('p')
+                    .long("port")
+                    .default_value("8080")
+                    .value_parser(clap::value_parser!(u16))
+            )
+            .arg(
+                Arg::new("host")
+                    .long("host")
+                    .help("address to listen on")
+                    .default_value("127.0.0.1")
+                    .value_parser(clap::value_parser!(std::net::IpAddr))
+            )
+            .arg(
+                Arg::new("fallback")
+                    .long("fallback")
+                    .help("model to try when the requested one fails, can be repeated")
+                    .value_name("MODEL")
+                    .action(clap::ArgAction::Append)
+            )
+            .arg(
+                Arg::new("token")
+                    .long("token")
+                    .help("bearer token clients must send, a random one is generated and printed if omitted")
+            )
+    );
+
+    cmd = cmd.subcommand(
+        Command::new("shell-init")
+            .about("print a snippet that binds Ctrl+G to gpt suggest, load it in your shell profile")
+            .arg(
+                Arg::new("shell")
+                    .required(true)
+                    .value_parser(shell::SHELLS.to_vec())
+            )
+    );
+
+    cmd = cmd.subcommand(
+        Command::new("suggest")
+            .about("turn a description into a shell command and print it, nothing is executed")
+            .arg(
+                Arg::new("text")
+                    .help("description or command to fix")
+                    .required(true)
+                    .num_args(1..)
+            )
+            .arg(
+                Arg::new("shell")
+                    .long("shell")
+                    .help("shell whose syntax is used, defaults to the system shell")
+                    .value_parser(shell::SHELLS.to_vec())
+            )
+            .arg(
+                Arg::new("model")
+                    .short('m')
+                    .long("model")
+                    .help("model to use, defaults to the current model")
+            )
+    );
+
+    cmd = cmd.subcommand(
+        Command::new("replay")
+            .about("parse a response recorded with --debug-http offline")
+            .arg(
+                Arg::new("log")
+                    .help("log file, defaults to the latest one")
+                    .value_parser(clap::value_parser!(PathBuf))
+            )
+            .arg(
+                Arg::new("events")
+                    .long("events")
+                    .help("print every parsed event instead of the answer")
+                    .action(clap::ArgAction::SetTrue)
+            )
+    );
+
+    cmd = cmd.subcommand(
+        Command::new("cache")
+            .about("response cache management")
+            .subcommand(
+                Command::new("stats")
+                    .about("show cache settings, entries and size")
+            )
+            .subcommand(
+                Command::new("clear")
+                    .about("remove all cached responses")
+            )
+    );
+
     cmd = cmd.subcommand(
         Command::new("agents")
             .about("agent management")
@@ -227,6 +713,38 @@ fn build_cli() -> Command {
     cmd
 }
 
+/// 命令行根命令已占用的参数，包括 clap 内置的 -h/--help 与 -V/--version
+fn reserved_flags(cmd: &Command) -> ReservedFlags {
+    let mut reserved = ReservedFlags {
+        shorts: vec!['h', 'V'],
+        longs: vec!["help".to_string(), "version".to_string()],
+    };
+    for arg in cmd.get_arguments() {
+        if let Some(short) = arg.get_short() {
+            reserved.shorts.push(short);
+        }
+        if let Some(long) = arg.get_long() {
+            reserved.longs.push(long.to_string());
+        }
+    }
+    reserved
+}
+
+/// 从命令行参数读取生成参数
+fn generation_params(matches: &clap::ArgMatches) -> GenerationParams {
+    GenerationParams {
+        temperature: matches.get_one::<f64>("temperature").copied(),
+        top_p: matches.get_one::<f64>("top_p").copied(),
+        max_tokens: matches.get_one::<u32>("max_tokens").copied(),
+        stop: matches
+            .get_many::<String>("stop")
+            .map(|values| values.cloned().collect()),
+        presence_penalty: matches.get_one::<f64>("presence_penalty").copied(),
+        frequency_penalty: matches.get_one::<f64>("frequency_penalty").copied(),
+        seed: matches.get_one::<u64>("seed").copied(),
+    }
+}
+
 /// 显示加载动画
 /// running: 控制动画是否继续运行的原子布尔值
 async fn loading_animation(running: Arc<AtomicBool>) {
@@ -244,31 +762,100 @@ async fn loading_animation(running: Arc<AtomicBool>) {
 
 /// 执行单次对话
 /// config: 程序配置
+/// bot: 使用的机器人，其固定的模型和参数优先于当前模型
 /// messages: 对话历消息
+/// cli_params: 命令行指定的生成参数，覆盖机器人和模型的默认值
+/// cli_schema: 命令行指定的输出 schema，覆盖机器人的 output_schema
 /// running: 控制对话是否继续的原子布尔值
 /// 返回助手的回复内容
-async fn chat_once(config: &Config, messages: Vec<Message>, running: Arc<AtomicBool>) -> Result<String> {
-    let (_, model_config) = match config.get_current_model() {
-        Some(model) => model,
-        None => {
-            println!("tips: no model configured, please add a model first.");
-            println!("you can use the following command to add a model:");
-            println!("  gpt config model add <n> <key> [--url <url>] [--model <model>]");
-            println!("for example, add deepseek:");
-            println!("  gpt config model add deepseek your-api-key --url https://api.deepseek.com/v1/chat/completions --model deepseek-chat");
-            return Ok(String::new());
-        }
+async fn chat_once(config: &Config, bot: Option<&Bot>, messages: Vec<Message>, cli_params: &GenerationParams, cli_schema: Option<&OutputSchema>, running: Arc<AtomicBool>) -> Result<String> {
+    let pinned_model = bot.and_then(|bot| bot.model.as_deref().map(|model| (bot, model)));
+    let model_config = match pinned_model {
+        Some((bot, name)) => config.get_model(name).ok_or_else(|| {
+            anyhow::anyhow!("model not found: {} (pinned by bot {})", name, bot.name)
+        })?,
+        None => match config.get_current_model() {
+            Some((_, model_config)) => model_config,
+            None => {
+                println!("tips: no model configured, please add a model first.");
+                println!("you can use the following command to add a model:");
+                println!("  gpt config model add <n> <key> [--url <url>] [--model <model>]");
+                println!("for example, add deepseek:");
+                println!("  gpt config model add deepseek your-api-key --url https://api.deepseek.com/v1/chat/completions --model deepseek-chat");
+                return Ok(String::new());
+            }
+        },
     };
 
-    let provider = Provider::new(model_config.api_key.clone())
-        .with_url(model_config.api_url.clone())
-        .with_model(model_config.model.clone());
+    let mut params = model_config.params.clone();
+    if let Some(bot) = bot {
+        params = params.merge(&bot.params);
+    }
+    let provider = config
+        .provider(model_config)?
+        .with_params(params.merge(cli_params))
+        .with_json_mode(bot.is_some_and(|bot| bot.output_format == OutputFormat::Json));
+
+    let schema = match cli_schema {
+        Some(schema) => Some(schema.clone()),
+        None => bot.map(Bot::output_schema).transpose()?.flatten(),
+    };
+    if let Some(schema) = schema {
+        return structured_reply(&provider, messages, &schema, running).await;
+    }
+
+    let mut response = String::new();
+    let mut continuations = 0;
+    loop {
+        // 续写时带上已生成的内容，让模型从中断处继续
+        let mut request = messages.clone();
+        if continuations > 0 {
+            request.push(Message::new("assistant", response.clone()));
+            request.push(Message::new("user", CONTINUE_PROMPT.to_string()));
+        }
+        let Some((part, finish)) = stream_reply(&provider, request, config.stream, !config.hide_reasoning, running.clone()).await? else {
+            return Ok(response);
+        };
+        response.push_str(&part);
+        if !running.load(Ordering::SeqCst) {
+            println!("\n{}", "cancelled".red());
+            return Ok(response);
+        }
 
+        match finish {
+            Some(FinishReason::Length) if continuations < config.auto_continue => {
+                continuations += 1;
+            }
+            Some(FinishReason::Length) => {
+                println!();
+                eprintln!(
+                    "{}",
+                    "[output truncated by max_tokens, raise --max-tokens or set auto_continue in config]".yellow()
+                );
+                return Ok(response);
+            }
+            Some(FinishReason::ContentFilter) => {
+                println!();
+                eprintln!("{}", "[output stopped by the provider's content filter]".yellow());
+                return Ok(response);
+            }
+            _ => break,
+        }
+    }
+    println!();
+
+    Ok(response)
+}
+
+/// 发送一次请求并输出回复，返回回复内容与结束原因
+/// 推理模型的思考过程以暗色输出到 stderr，不计入回复内容，也就不会进入对话历史
+/// 在收到响应前被取消时返回 None，输出过程中服务端出错时返回 Err
+async fn stream_reply(provider: &Provider, messages: Vec<Message>, stream: bool, show_reasoning: bool, running: Arc<AtomicBool>) -> Result<Option<(String, Option<FinishReason>)>> {
     let loading_running = Arc::new(AtomicBool::new(true));
     let loading_handle = tokio::spawn(loading_animation(loading_running.clone()));
 
     let stream_result = select! {
-        result = provider.chat(messages, config.stream, running.clone()) => {
+        result = provider.chat(messages, stream, running.clone()) => {
             loading_running.store(false, Ordering::SeqCst);
             let _ = loading_handle.await;
             result
@@ -281,50 +868,176 @@ async fn chat_once(config: &Config, messages: Vec<Message>, running: Arc<AtomicB
             loading_running.store(false, Ordering::SeqCst);
             let _ = loading_handle.await;
             println!("\n{}", "cancelled".red());
-            return Ok(String::new());
+            return Ok(None);
         }
     };
 
     let mut stream = stream_result?;
     let mut response = String::new();
+    let mut finish = None;
+    let mut reasoning = false;
 
     while let Some(result) = stream.next().await {
         if !running.load(Ordering::SeqCst) {
-            println!("\n{}", "cancelled".red());
-            return Ok(response);
+            break;
         }
 
         match result {
-            Ok(content) if !content.is_empty() => {
+            // 思考过程输出到 stderr，重定向 stdout 时只得到回复
+            ChatEvent::ReasoningDelta(content) if show_reasoning => {
+                if !reasoning {
+                    reasoning = true;
+                    eprintln!("{}", "thinking:".dimmed());
+                }
+                eprint!("{}", content.dimmed());
+                io::stderr().flush()?;
+            }
+            ChatEvent::TextDelta(content) => {
+                // 思考过程结束，与回复之间空一行
+                if reasoning {
+                    reasoning = false;
+                    eprintln!("\n");
+                }
                 print!("{}", content.green());
                 io::stdout().flush()?;
                 response.push_str(&content);
             }
-            Err(e) => {
-                eprintln!("\nerror: {}", e);
-                break;
+            ChatEvent::Finish(reason) => finish = Some(reason),
+            // 中途出错时已输出的部分不算作回复，交给调用方撤回本轮对话
+            ChatEvent::Error(e) => {
+                println!();
+                return Err(e.into());
             }
             _ => {}
         }
     }
 
-    if !running.load(Ordering::SeqCst) {
-        println!("\n{}", "cancelled".red());
-    } else {
-        println!();
+    Ok(Some((response, finish)))
+}
+
+/// 按 JSON Schema 请求结构化输出，只把通过校验的 JSON 输出到 stdout
+/// 服务端不支持 json_schema 时退回 json_object 模式并把 schema 写入提示词
+/// 校验失败时带上错误信息重新请求，最多 SCHEMA_RETRIES 次
+async fn structured_reply(provider: &Provider, mut messages: Vec<Message>, schema: &OutputSchema, running: Arc<AtomicBool>) -> Result<String> {
+    let mut provider = provider.clone().with_output_schema(Some(schema.clone()));
+    let mut schema_mode = true;
+    let mut attempt = 0;
+    loop {
+        let reply = match collect_reply(&provider, messages.clone(), running.clone()).await {
+            Ok(Some(reply)) => reply,
+            Ok(None) => {
+                eprintln!("{}", "cancelled".red());
+                return Ok(String::new());
+            }
+            Err(e) if schema_mode && schema::is_unsupported(&e) => {
+                eprintln!("{}", format!("json_schema is not supported ({}), falling back to json mode", e).dimmed());
+                schema_mode = false;
+                provider = provider.with_output_schema(None).with_json_mode(true);
+                let position = messages.iter().take_while(|message| message.role == "system").count();
+                messages.insert(
+                    position,
+                    Message::new(
+                        "system",
+                        format!(
+                            "Respond only with JSON that matches this JSON Schema:\n{}",
+                            serde_json::to_string_pretty(&schema.schema)?
+                        ),
+                    ),
+                );
+                continue;
+            }
+            Err(e) => return Err(e),
+        };
+
+        let errors = match schema::parse_reply(&reply) {
+            Ok(value) => {
+                let errors = schema.validate(&value);
+                if errors.is_empty() {
+                    println!("{}", serde_json::to_string_pretty(&value)?);
+                    return Ok(serde_json::to_string(&value)?);
+                }
+                errors
+            }
+            Err(e) => vec![format!("invalid JSON: {}", e)],
+        };
+        if attempt >= SCHEMA_RETRIES {
+            for error in &errors {
+                eprintln!("  {}", error);
+            }
+            return Err(anyhow::anyhow!(
+                "response does not match the schema after {} attempt(s)",
+                attempt + 1
+            ));
+        }
+        eprintln!(
+            "{}",
+            format!("response does not match the schema ({} error(s)), retrying", errors.len()).yellow()
+        );
+        let feedback = errors.iter().map(|error| format!("- {}", error)).collect::<Vec<_>>().join("\n");
+        messages.push(Message::new("assistant", reply));
+        messages.push(Message::new(
+            "user",
+            format!(
+                "Your response does not match the JSON Schema:\n{}\nReply again with only the corrected JSON.",
+                feedback
+            ),
+        ));
+        attempt += 1;
     }
+}
 
-    Ok(response)
+/// 不输出内容地获取完整回复，在收到响应前被取消时返回 None
+async fn collect_reply(provider: &Provider, messages: Vec<Message>, running: Arc<AtomicBool>) -> Result<Option<String>> {
+    let events = select! {
+        result = provider.chat(messages, false, running.clone()) => result?,
+        _ = async {
+            while running.load(Ordering::SeqCst) {
+                sleep(Duration::from_millis(50)).await;
+            }
+        } => return Ok(None),
+    };
+    let mut chunks = llm_provider::text_stream(events);
+    let mut reply = String::new();
+    while let Some(chunk) = chunks.next().await {
+        reply.push_str(&chunk?);
+    }
+    Ok(Some(reply))
 }
 
 /// 交互式对话模式
 /// config: 程序配置
 /// bot_name: 指定使用的机器人名称
 /// bots_config: 机器人配置
+/// cli_params: 命令行指定的生成参数
+/// cli_schema: 命令行指定的输出 schema
+/// template_ctx: 机器人提示词模板变量
 /// running: 控制程序是否继续运行的原子布尔值
-async fn interactive_mode(config: Config, bot_name: Option<String>, bots_config: BotsConfig, running: Arc<AtomicBool>) -> Result<()> {
-    // 首先检查是否配置了模型
-    if config.get_current_model().is_none() {
+async fn interactive_mode(config: Config, bot_name: Option<String>, bots_config: BotsConfig, cli_params: GenerationParams, cli_schema: Option<OutputSchema>, template_ctx: TemplateContext, running: Arc<AtomicBool>) -> Result<()> {
+    let bot = match bot_name {
+        Some(bot_name) => match bots_config.get_bot(&bot_name) {
+            Some(bot) => {
+                println!("using bot: {}", bot_name.green());
+                Some(bot)
+            }
+            None => {
+                println!("tips: bot not found: {}", bot_name);
+                println!("you can use the following command to list all bots:");
+                println!("  gpt bots list");
+                return Ok(());
+            }
+        },
+        None => {
+            // use current bot if set
+            let bot = bots_config.get_current();
+            if let Some(bot) = bot {
+                println!("using current bot: {}", bot.name.green());
+            }
+            bot
+        }
+    };
+
+    // 首先检查是否配置了模型（机器人固定了模型时除外）
+    if bot.and_then(|bot| bot.model.as_ref()).is_none() && config.get_current_model().is_none() {
         println!("tips: no model configured, please add a model first.");
         println!("you can use the following command to add a model:");
         println!("  gpt config model add <n> <key> [--url <url>] [--model <model>]");
@@ -334,27 +1047,16 @@ async fn interactive_mode(config: Config, bot_name: Option<String>, bots_config:
     }
 
     let mut messages = Vec::new();
-    // if specified bot, use bot's system prompt
-    if let Some(bot_name) = bot_name {
-        if let Some(bot) = bots_config.get_bot(&bot_name) {
-            messages.push(create_message("system", bot.system_prompt.clone()));
-            println!("using bot: {}", bot_name.green());
-        } else {
-            println!("tips: bot not found: {}", bot_name);
-            println!("you can use the following command to list all bots:");
-            println!("  gpt bots list");
-            return Ok(());
-        }
-    } else if let Some(bot) = bots_config.get_current() {
-        // use current bot if set
-        messages.push(create_message("system", bot.system_prompt.clone()));
-        println!("using current bot: {}", bot.name.green());
-    } else if let Some(ref system_prompt) = config.system_prompt {
+    if let Some(bot) = bot {
+        // use bot's system prompt and examples
+        messages.extend(bot.initial_messages(&template_ctx)?);
+    } else if let Some(system_prompt) = config.get_system_prompt() {
         // otherwise use default system prompt
-        messages.push(create_message("system", system_prompt.clone()));
+        messages.push(Message::new("system", system_prompt.clone()));
     }
 
-    println!("enter interactive mode (input 'exit' or press Ctrl+C to exit)");
+    let initial_len = messages.len();
+    println!("enter interactive mode (input 'exit' or press Ctrl+C to exit, '/clear' to start over)");
     println!("---------------------------------------------");
 
     let stdin = io::stdin();
@@ -380,16 +1082,40 @@ async fn interactive_mode(config: Config, bot_name: Option<String>, bots_config:
         if input == "exit" {
             break;
         }
+        if input == "/clear" {
+            messages.truncate(initial_len);
+            println!("conversation cleared");
+            continue;
+        }
 
-        // add user message
-        messages.push(create_message("user", input.to_string()));
+        // add user message, rendered by bot's user template if any
+        let input = match bot {
+            Some(bot) => match bot.render_input(input, &template_ctx) {
+                Ok(input) => input,
+                Err(e) => {
+                    eprintln!("error: {}", e);
+                    continue;
+                }
+            },
+            None => input.to_string(),
+        };
+        messages.push(Message::new("user", input));
 
         // get assistant response
-        let response = chat_once(&config, messages.clone(), running.clone()).await?;
+        let response = match chat_once(&config, bot, messages.clone(), &cli_params, cli_schema.as_ref(), running.clone()).await {
+            Ok(response) => response,
+            // 服务端错误不退出对话，撤回本轮输入以便重试或 /clear
+            Err(e) if e.downcast_ref::<ProviderError>().is_some() => {
+                eprintln!("{} {}", "error:".red(), e);
+                messages.pop();
+                continue;
+            }
+            Err(e) => return Err(e),
+        };
 
         // only add to history if there is a response
         if !response.is_empty() {
-            messages.push(create_message("assistant", response));
+            messages.push(Message::new("assistant", response));
         }
     }
 
@@ -408,39 +1134,76 @@ async fn main() -> Result<()> {
     // load environment variables
     dotenv().ok();
     
+    // `gpt config doctor` 在加载配置之前处理：加载会升级并写回旧版本文件，
+    // doctor 需要看到原始文件，也要在配置无法加载时正常运行，且不修改任何文件
+    let args: Vec<String> = std::env::args().collect();
+    let cli = build_cli();
+    if let Ok(matches) = cli.clone().ignore_errors(true).try_get_matches_from(&args) {
+        if let Some(("config", sub_matches)) = matches.subcommand() {
+            if let Some(("doctor", _)) = sub_matches.subcommand() {
+                return doctor::run(&reserved_flags(&cli));
+            }
+        }
+    }
+
     // load config
-    let mut config = Config::load()?;
-    let mut bots_config = BotsConfig::load()?;
+    let config = Config::load();
+    let bots_config = BotsConfig::load();
+    let loaded_bots = bots_config.as_ref().ok();
     
     // build and get command line arguments
-    let mut cmd = build_cli();
-    
-    // 为每个别名添加短参数
-    let aliases: Vec<(String, String)> = bots_config.aliases.iter()
-        .map(|(k, v)| (k.clone(), v.clone()))
-        .collect();
+    let mut cmd = cli;
     
-    // 添加别名参数
-    let mut alias_args = Vec::new();
-    for (alias, _) in &aliases {
-        if let Some(c) = alias.chars().next() {
-            let id = format!("alias_{}", alias);
-            let id_static = Box::leak(id.into_boxed_str()) as &'static str;
-            alias_args.push(
-                Arg::new(id_static)
-                    .short(c)
-                    .help(format!("use bot alias '{}'", alias))
-                    .action(clap::ArgAction::SetTrue)
+    // 为每个别名添加参数：单字符别名为 -x，多字符别名为 --name
+    let reserved = reserved_flags(&cmd);
+    let mut aliases: Vec<(String, String)> = loaded_bots
+        .map(|bots_config| bots_config.all_aliases().into_iter().collect())
+        .unwrap_or_default();
+    aliases.sort();
+    aliases.retain(|(alias, _)| match bots::check_alias(alias, &reserved) {
+        Ok(()) => true,
+        Err(e) => {
+            eprintln!(
+                "{} {}, ignored (remove it with `gpt bots alias remove {}`)",
+                "warning:".yellow(),
+                e,
+                alias
             );
+            false
         }
+    });
+    for (alias, bot) in &aliases {
+        let arg = Arg::new(format!("alias_{}", alias))
+            .help(format!("use bot '{}'", bot))
+            .action(clap::ArgAction::SetTrue);
+        let mut chars = alias.chars();
+        cmd = cmd.arg(match (chars.next(), chars.next()) {
+            (Some(c), None) => arg.short(c),
+            _ => arg.long(alias.clone()),
+        });
     }
-    
-    // 将别名参数添加到命令中
-    for arg in alias_args {
-        cmd = cmd.arg(arg);
+
+    // 支持 `gpt :<alias> prompt` 语法，也可以直接写机器人名称
+    // 不是已知别名或机器人时按普通提示词处理，例如 `gpt ":) explain"`
+    let mut args = args;
+    let bot_name = args
+        .get(1)
+        .and_then(|arg| arg.strip_prefix(':'))
+        .and_then(|name| {
+            let bots_config = loaded_bots?;
+            bots_config
+                .get_bot_by_alias(name)
+                .cloned()
+                .or_else(|| bots_config.get_bot(name).map(|_| name.to_string()))
+        });
+    if bot_name.is_some() {
+        args.remove(1);
     }
-    
-    let matches = cmd.get_matches();
+    let mut bot_name = bot_name;
+
+    let matches = cmd.get_matches_from(args);
+    let mut config = config?;
+    let mut bots_config = bots_config?;
 
     // set interrupt handler
     let running = Arc::new(AtomicBool::new(true));
@@ -451,15 +1214,11 @@ async fn main() -> Result<()> {
     })?;
 
     // 检查否使用了别名
-    let mut bot_name = None;
-    for (alias, _) in &aliases {
-        let id = format!("alias_{}", alias);
-        if matches.get_flag(&id) {
-            if let Some(bot) = bots_config.get_bot_by_alias(alias) {
-                bot_name = Some(bot.to_string());
-                break;
-            }
-        }
+    if bot_name.is_none() {
+        bot_name = aliases
+            .iter()
+            .find(|(alias, _)| matches.get_flag(&format!("alias_{}", alias)))
+            .map(|(_, bot)| bot.clone());
     }
 
     // 如果没有使用别名，检查是否使用了 --bot 参数
@@ -467,6 +1226,32 @@ async fn main() -> Result<()> {
         bot_name = matches.get_one::<String>("bot").cloned();
     }
 
+    let cli_params = generation_params(&matches);
+    if let Some(limit) = matches.get_one::<u32>("auto_continue") {
+        config.auto_continue = *limit;
+    }
+    let cli_schema = matches
+        .get_one::<PathBuf>("schema")
+        .map(|path| OutputSchema::load(path))
+        .transpose()?;
+    if matches.get_flag("hide_reasoning") {
+        config.hide_reasoning = true;
+    }
+    if matches.get_flag("debug_http") && config.log_dir.is_none() {
+        config.log_dir = http_log::HttpLog::default_dir();
+    }
+    if matches.get_flag("no_cache") {
+        if let Some(cache) = config.cache.as_mut() {
+            cache.enabled = false;
+        }
+    }
+    let template_ctx = TemplateContext::new(
+        matches
+            .get_many::<(String, String)>("var")
+            .map(|vars| vars.cloned().collect())
+            .unwrap_or_default(),
+    );
+
     // 处理子命令或提示词
     match matches.subcommand() {
         Some(("config", sub_matches)) => {
@@ -474,20 +1259,52 @@ async fn main() -> Result<()> {
                 Some(("model", model_matches)) => {
                     match model_matches.subcommand() {
                         Some(("add", add_matches)) => {
-                            if let (Some(name), Some(key), Some(url), Some(model)) = (
-                                add_matches.get_one::<String>("name"),
-                                add_matches.get_one::<String>("key"),
-                                add_matches.get_one::<String>("url"),
-                                add_matches.get_one::<String>("model")
-                            ) {
+                            if let Some(name) = add_matches.get_one::<String>("name") {
                                 config.add_model(
                                     name.clone(),
-                                    key.clone(),
-                                    url.clone(),
-                                    model.clone()
+                                    add_matches.get_one::<String>("provider").map(String::as_str),
+                                    add_matches.get_one::<String>("key").cloned(),
+                                    add_matches.get_one::<String>("url").cloned(),
+                                    add_matches.get_one::<String>("model").cloned()
                                 )?;
                             }
                         }
+                        Some(("set", set_matches)) => {
+                            if let Some(name) = set_matches.get_one::<String>("name") {
+                                config.set_model(
+                                    name,
+                                    set_matches.get_one::<String>("key").cloned(),
+                                    set_matches.get_one::<String>("url").cloned(),
+                                    set_matches.get_one::<String>("model").cloned(),
+                                    &generation_params(set_matches)
+                                )?;
+                            }
+                        }
+                        Some(("test", test_matches)) => {
+                            probe::run(
+                                &config,
+                                test_matches.get_one::<String>("name").map(String::as_str),
+                                test_matches.get_flag("all"),
+                                running.clone()
+                            ).await?;
+                        }
+                        Some(("rename", rename_matches)) => {
+                            if let (Some(name), Some(new_name)) = (
+                                rename_matches.get_one::<String>("name"),
+                                rename_matches.get_one::<String>("new_name")
+                            ) {
+                                config.rename_model(name, new_name)?;
+                                bots_config.rename_model(name, new_name)?;
+                            }
+                        }
+                        Some(("copy", copy_matches)) => {
+                            if let (Some(name), Some(new_name)) = (
+                                copy_matches.get_one::<String>("name"),
+                                copy_matches.get_one::<String>("new_name")
+                            ) {
+                                config.copy_model(name, new_name)?;
+                            }
+                        }
                         Some(("remove", remove_matches)) => {
                             if let Some(name) = remove_matches.get_one::<String>("name") {
                                 config.remove_model(name)?;
@@ -503,7 +1320,11 @@ async fn main() -> Result<()> {
                         }
                         _ => {
                             println!("available model commands:");
-                            println!("  gpt config model add <n> <key> [--url <url>] [--model <model>]");
+                            println!("  gpt config model add <n> [key] [--provider <kind>] [--url <url>] [--model <model>]");
+                            println!("  gpt config model set <n> [--key <key>] [--url <url>] [--model <model>] [--temperature ...]");
+                            println!("  gpt config model test [n|--all]");
+                            println!("  gpt config model rename <n> <new-name>");
+                            println!("  gpt config model copy <n> <new-name>");
                             println!("  gpt config model remove <n>");
                             println!("  gpt config model list");
                             println!("  gpt config model use <n>");
@@ -519,6 +1340,35 @@ async fn main() -> Result<()> {
                         config.set_stream(enabled.parse()?)?;
                     }
                 }
+                Some(("show", show_matches)) if show_matches.contains_id("format") => {
+                    let value = config.to_redacted_value()?;
+                    match show_matches.get_one::<String>("format").map(String::as_str) {
+                        Some("json") => println!("{}", serde_json::to_string_pretty(&value)?),
+                        _ => print!("{}", toml::to_string_pretty(&value)?),
+                    }
+                }
+                Some(("get", get_matches)) => {
+                    if let Some(key) = get_matches.get_one::<String>("key") {
+                        match config.get_value(key)? {
+                            toml::Value::String(value) => println!("{}", value),
+                            toml::Value::Table(table) => print!("{}", toml::to_string_pretty(&table)?),
+                            value => println!("{}", value),
+                        }
+                    }
+                }
+                Some(("set", set_matches)) => {
+                    if let (Some(key), Some(value)) = (
+                        set_matches.get_one::<String>("key"),
+                        set_matches.get_one::<String>("value")
+                    ) {
+                        config.set_value(key, value)?;
+                    }
+                }
+                Some(("unset", unset_matches)) => {
+                    if let Some(key) = unset_matches.get_one::<String>("key") {
+                        config.unset_value(key)?;
+                    }
+                }
                 Some(("show", _)) => {
                     println!("current config:");
                     if let Some((name, model_config)) = config.get_current_model() {
@@ -526,9 +1376,12 @@ async fn main() -> Result<()> {
                         println!("  api url: {}", model_config.api_url);
                         println!("  model: {}", model_config.model);
                         println!("  stream: {}", config.stream);
-                        if let Some(ref system_prompt) = config.system_prompt {
+                        if let Some(system_prompt) = config.get_system_prompt() {
                             println!("  system prompt: {}", system_prompt);
                         }
+                        if let Some(project) = &config.project {
+                            println!("  project config: {}", project.path.display());
+                        }
                     } else {
                         println!("  no model configured");
                     }
@@ -544,9 +1397,12 @@ async fn main() -> Result<()> {
                         println!("  api url: {}", model_config.api_url);
                         println!("  model: {}", model_config.model);
                         println!("  stream: {}", config.stream);
-                        if let Some(ref system_prompt) = config.system_prompt {
+                        if let Some(system_prompt) = config.get_system_prompt() {
                             println!("  system prompt: {}", system_prompt);
                         }
+                        if let Some(project) = &config.project {
+                            println!("  project config: {}", project.path.display());
+                        }
                     } else {
                         println!("  no model configured");
                     }
@@ -560,7 +1416,21 @@ async fn main() -> Result<()> {
                         add_matches.get_one::<String>("name"),
                         add_matches.get_one::<String>("system")
                     ) {
-                        bots_config.add_bot(name.clone(), system.clone())?;
+                        let output_format = match add_matches.get_one::<String>("format") {
+                            Some(format) => format.parse()?,
+                            None => OutputFormat::Text,
+                        };
+                        bots_config.add_bot(Bot {
+                            name: name.clone(),
+                            system_prompt: system.clone(),
+                            model: add_matches.get_one::<String>("model").cloned(),
+                            output_format,
+                            output_schema: add_matches
+                                .get_one::<PathBuf>("schema")
+                                .map(|path| OutputSchema::load(path).map(|schema| schema.schema))
+                                .transpose()?,
+                            ..Default::default()
+                        })?;
                     }
                 }
                 Some(("remove", remove_matches)) => {
@@ -582,6 +1452,52 @@ async fn main() -> Result<()> {
                 Some(("clear", _)) => {
                     bots_config.clear_current()?;
                 }
+                Some(("export", export_matches)) => {
+                    if let Some(name) = export_matches.get_one::<String>("name") {
+                        bots_config.export_bot(name, export_matches.get_one::<PathBuf>("output"))?;
+                    }
+                }
+                Some(("import", import_matches)) => {
+                    if let (Some(path), Some(strategy)) = (
+                        import_matches.get_one::<PathBuf>("path"),
+                        import_matches.get_one::<String>("on_conflict")
+                    ) {
+                        let strategy: ConflictStrategy = strategy.parse()?;
+                        bots_config.import_bots(path, strategy, &reserved)?;
+                    }
+                }
+                Some(("example", example_matches)) => {
+                    match example_matches.subcommand() {
+                        Some(("add", add_matches)) => {
+                            if let (Some(bot), Some(user), Some(assistant)) = (
+                                add_matches.get_one::<String>("bot"),
+                                add_matches.get_one::<String>("user"),
+                                add_matches.get_one::<String>("assistant")
+                            ) {
+                                bots_config.add_example(bot, user.clone(), assistant.clone())?;
+                            }
+                        }
+                        Some(("list", list_matches)) => {
+                            if let Some(bot) = list_matches.get_one::<String>("bot") {
+                                bots_config.list_examples(bot)?;
+                            }
+                        }
+                        Some(("remove", remove_matches)) => {
+                            if let (Some(bot), Some(index)) = (
+                                remove_matches.get_one::<String>("bot"),
+                                remove_matches.get_one::<usize>("index")
+                            ) {
+                                bots_config.remove_example(bot, *index)?;
+                            }
+                        }
+                        _ => {
+                            println!("available example commands:");
+                            println!("  gpt bots example add <bot> <user> <assistant>  # add example");
+                            println!("  gpt bots example list <bot>                    # list examples");
+                            println!("  gpt bots example remove <bot> <index>          # remove example");
+                        }
+                    }
+                }
                 Some(("alias", alias_matches)) => {
                     match alias_matches.subcommand() {
                         Some(("set", set_matches)) => {
@@ -589,7 +1505,7 @@ async fn main() -> Result<()> {
                                 set_matches.get_one::<String>("bot"),
                                 set_matches.get_one::<String>("alias")
                             ) {
-                                bots_config.set_alias(bot.clone(), alias.clone())?;
+                                bots_config.set_alias(bot.clone(), alias.clone(), &reserved)?;
                             }
                         }
                         Some(("remove", remove_matches)) => {
@@ -614,6 +1530,167 @@ async fn main() -> Result<()> {
                 }
             }
         }
+        Some(("registry", sub_matches)) => {
+            let mut registry_config = RegistryConfig::load()?;
+            match sub_matches.subcommand() {
+                Some(("add", add_matches)) => {
+                    if let Some(source) = add_matches.get_one::<String>("source") {
+                        registry_config.add(
+                            add_matches.get_one::<String>("name").cloned(),
+                            source.clone(),
+                            add_matches.get_one::<String>("pin").cloned(),
+                        )?;
+                    }
+                }
+                Some(("remove", remove_matches)) => {
+                    if let Some(name) = remove_matches.get_one::<String>("name") {
+                        registry_config.remove(name)?;
+                    }
+                }
+                Some(("list", _)) => {
+                    registry_config.list();
+                }
+                Some(("update", update_matches)) => {
+                    registry_config.update(update_matches.get_one::<String>("name").map(|s| s.as_str()))?;
+                }
+                Some(("pin", pin_matches)) => {
+                    if let Some(name) = pin_matches.get_one::<String>("name") {
+                        registry_config.pin(name, pin_matches.get_one::<String>("revision").cloned())?;
+                    }
+                }
+                _ => {
+                    registry_config.list();
+                }
+            }
+        }
+        Some(("project", sub_matches)) => {
+            let path = || {
+                utils::find_project_config()
+                    .ok_or_else(|| anyhow::anyhow!("no project config found from the current directory"))
+            };
+            match sub_matches.subcommand() {
+                Some(("trust", _)) => TrustedProjects::set(&path()?, true)?,
+                Some(("untrust", _)) => TrustedProjects::set(&path()?, false)?,
+                _ => TrustedProjects::print_status()?,
+            }
+        }
+        Some(("compare", compare_matches)) => {
+            let names: Vec<String> = compare_matches
+                .get_many::<String>("model")
+                .map(|values| values.cloned().collect())
+                .unwrap_or_default();
+            let layout = if compare_matches.get_flag("columns") {
+                compare::Layout::Columns
+            } else {
+                compare::Layout::Sequential
+            };
+            if let Some(prompt) = compare_matches.get_one::<String>("prompt") {
+                compare::run(
+                    &config,
+                    &names,
+                    prompt,
+                    &generation_params(compare_matches),
+                    compare_matches.get_one::<String>("judge").map(String::as_str),
+                    layout,
+                    running.clone()
+                ).await?;
+            }
+        }
+        Some(("batch", batch_matches)) => {
+            let bot = match batch_matches.get_one::<String>("bot") {
+                Some(name) => Some(
+                    bots_config
+                        .get_bot(name)
+                        .ok_or_else(|| anyhow::anyhow!("bot not found: {}", name))?,
+                ),
+                None => None,
+            };
+            if let (Some(input), Some(output)) = (
+                batch_matches.get_one::<PathBuf>("input"),
+                batch_matches.get_one::<PathBuf>("output")
+            ) {
+                let format = match batch_matches.get_one::<String>("format") {
+                    Some(format) => format.parse()?,
+                    None => batch::InputFormat::detect(input),
+                };
+                let options = batch::BatchOptions {
+                    input: input.clone(),
+                    output: output.clone(),
+                    format,
+                    model: batch_matches.get_one::<String>("model").cloned(),
+                    bot,
+                    template: batch_matches.get_one::<String>("template").cloned(),
+                    concurrency: *batch_matches.get_one::<usize>("concurrency").unwrap_or(&4),
+                    retries: *batch_matches.get_one::<u32>("retries").unwrap_or(&3),
+                    params: generation_params(batch_matches),
+                    template_ctx: template_ctx.clone(),
+                };
+                batch::run(&config, options, running.clone()).await?;
+            }
+        }
+        Some(("serve", serve_matches)) => {
+            let host = *serve_matches
+                .get_one::<std::net::IpAddr>("host")
+                .ok_or_else(|| anyhow::anyhow!("missing host"))?;
+            let port = *serve_matches
+                .get_one::<u16>("port")
+                .ok_or_else(|| anyhow::anyhow!("missing port"))?;
+            let options = serve::ServeOptions {
+                addr: std::net::SocketAddr::new(host, port),
+                fallback: serve_matches
+                    .get_many::<String>("fallback")
+                    .map(|values| values.cloned().collect())
+                    .unwrap_or_default(),
+                token: serve_matches.get_one::<String>("token").cloned(),
+            };
+            serve::run(config, bots_config, options, running.clone()).await?;
+        }
+        Some(("shell-init", init_matches)) => {
+            if let Some(shell) = init_matches.get_one::<String>("shell") {
+                print!("{}", shell::init_script(shell)?);
+            }
+        }
+        Some(("suggest", suggest_matches)) => {
+            let text = suggest_matches
+                .get_many::<String>("text")
+                .map(|words| words.cloned().collect::<Vec<_>>().join(" "))
+                .unwrap_or_default();
+            shell::suggest(
+                &config,
+                suggest_matches.get_one::<String>("model").map(String::as_str),
+                &text,
+                suggest_matches.get_one::<String>("shell").map(String::as_str),
+                running.clone()
+            ).await?;
+        }
+        Some(("replay", replay_matches)) => {
+            let path = match replay_matches.get_one::<PathBuf>("log") {
+                Some(path) => path.clone(),
+                None => {
+                    let dir = config
+                        .log_dir
+                        .clone()
+                        .or_else(http_log::HttpLog::default_dir)
+                        .ok_or_else(|| anyhow::anyhow!("can not find the log directory"))?;
+                    http_log::latest(&dir)?
+                }
+            };
+            http_log::replay(&path, replay_matches.get_flag("events")).await?;
+        }
+        Some(("cache", sub_matches)) => {
+            let cache = config
+                .response_cache()
+                .ok_or_else(|| anyhow::anyhow!("can not find the config directory"))?;
+            match sub_matches.subcommand() {
+                Some(("clear", _)) => {
+                    let removed = cache.clear()?;
+                    println!("removed {} cached response(s)", removed);
+                }
+                _ => {
+                    cache.print_stats(config.cache_enabled())?;
+                }
+            }
+        }
         Some(("agents", sub_matches)) => {
             match sub_matches.subcommand() {
                 Some(("add", add_matches)) => {
@@ -622,6 +1699,7 @@ async fn main() -> Result<()> {
                         add_matches.get_one::<String>("system")
                     ) {
                         let agent = Agent {
+                            version: migrate::AGENT_VERSION,
                             name: name.clone(),
                             description: None,
                             system_prompt: system.clone(),
@@ -726,30 +1804,35 @@ async fn main() -> Result<()> {
                     // 单次对话模式
                     let mut messages = Vec::new();
 
-                    if let Some(bot_name) = &bot_name {
+                    let bot = match &bot_name {
                         // 如果指定了机器人，使用机器人的系统提示词
-                        if let Some(bot) = bots_config.get_bot(bot_name) {
-                            messages.push(create_message("system", bot.system_prompt.clone()));
-                        } else {
-                            return Err(anyhow::anyhow!("未找到机器人: {}", bot_name));
-                        }
-                    } else if let Some(bot) = bots_config.get_current() {
+                        Some(bot_name) => Some(
+                            bots_config
+                                .get_bot(bot_name)
+                                .ok_or_else(|| anyhow::anyhow!("未找到机器人: {}", bot_name))?,
+                        ),
                         // 使用当前机器人
-                        messages.push(create_message("system", bot.system_prompt.clone()));
-                    } else if let Some(ref system_prompt) = config.system_prompt {
+                        None => bots_config.get_current(),
+                    };
+
+                    let mut user_message = prompt.clone();
+                    if let Some(bot) = bot {
+                        messages.extend(bot.initial_messages(&template_ctx)?);
+                        user_message = bot.render_input(prompt, &template_ctx)?;
+                    } else if let Some(system_prompt) = config.get_system_prompt() {
                         // 否则用默认系统提示词
-                        messages.push(create_message("system", system_prompt.clone()));
+                        messages.push(Message::new("system", system_prompt.clone()));
                     }
 
                     // 添加用户消息
-                    messages.push(create_message("user", prompt.clone()));
+                    messages.push(Message::new("user", user_message));
 
                     // 发送消息并获取回复
-                    chat_once(&config, messages, running).await?;
+                    chat_once(&config, bot, messages, &cli_params, cli_schema.as_ref(), running).await?;
                 }
             } else {
                 // 交互模式
-                interactive_mode(config, bot_name, bots_config, running).await?;
+                interactive_mode(config, bot_name, bots_config, cli_params, cli_schema, template_ctx, running).await?;
             }
         }
     }
@@ -0,0 +1,186 @@
+use anyhow::{Context, Result};
+use crate::utils;
+use std::fs;
+use std::path::Path;
+use toml_edit::{value, DocumentMut, Item, Table};
+
+/// 配置文件类型，每种类型有各自的版本号与迁移步骤
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Schema {
+    Config,
+    Bots,
+    Agent,
+}
+
+/// 将 `version` 升级到 `version + 1` 的迁移步骤
+/// 直接修改文档，保留用户文件中的注释与顺序
+type Migration = fn(&mut DocumentMut) -> Result<()>;
+
+pub const CONFIG_VERSION: u32 = 1;
+pub const BOTS_VERSION: u32 = 1;
+pub const AGENT_VERSION: u32 = 1;
+
+impl Schema {
+    pub fn current_version(self) -> u32 {
+        match self {
+            Schema::Config => CONFIG_VERSION,
+            Schema::Bots => BOTS_VERSION,
+            Schema::Agent => AGENT_VERSION,
+        }
+    }
+
+    /// 按起始版本排列的迁移步骤，长度等于当前版本号
+    fn migrations(self) -> &'static [Migration] {
+        match self {
+            Schema::Config => &[config_v0_to_v1],
+            Schema::Bots => &[bots_v0_to_v1],
+            Schema::Agent => &[agent_v0_to_v1],
+        }
+    }
+}
+
+/// 0.3.x 及更早版本的配置没有 version 字段，内容与 v1 相同
+fn config_v0_to_v1(_doc: &mut DocumentMut) -> Result<()> {
+    Ok(())
+}
+
+/// 早期版本的 bots.toml 在没有机器人时缺少 [bots] 表，无法解析
+/// 早期的系统提示词不是模板，其中的 `{{` 需要转义为 `\{{` 才能按原文发送
+fn bots_v0_to_v1(doc: &mut DocumentMut) -> Result<()> {
+    if !doc.contains_key("bots") {
+        doc.insert("bots", Item::Table(Table::new()));
+    }
+    let bots = doc["bots"]
+        .as_table_like_mut()
+        .ok_or_else(|| anyhow::anyhow!("bots: expected a table"))?;
+    for (_, bot) in bots.iter_mut() {
+        let Some(prompt) = bot.get_mut("system_prompt").and_then(Item::as_value_mut) else {
+            continue;
+        };
+        let Some(escaped) = prompt.as_str().filter(|text| text.contains("{{")).map(|text| text.replace("{{", "\\{{")) else {
+            continue;
+        };
+        // 保留原有的空白与注释
+        let decor = prompt.decor().clone();
+        *prompt = escaped.into();
+        *prompt.decor_mut() = decor;
+    }
+    Ok(())
+}
+
+/// 0.3.x 及更早版本的 agent 文件没有 version 字段，内容与 v1 相同
+fn agent_v0_to_v1(_doc: &mut DocumentMut) -> Result<()> {
+    Ok(())
+}
+
+/// 读取文件中的版本号，没有 version 字段视为版本 0
+pub fn file_version(table: &Table) -> Result<u32> {
+    match table.get("version") {
+        None => Ok(0),
+        Some(item) => item
+            .as_integer()
+            .filter(|v| *v >= 0)
+            .map(|v| v as u32)
+            .ok_or_else(|| anyhow::anyhow!("version: expected a non-negative integer")),
+    }
+}
+
+/// 将配置内容升级到当前版本，返回升级后的内容
+/// persist 为 true 时会先备份为 `<file>.v<旧版本>.bak`，再原地写回升级后的文件
+pub fn upgrade(schema: Schema, path: &Path, content: &str, persist: bool) -> Result<String> {
+    let mut doc: DocumentMut = content
+        .parse()
+        .with_context(|| format!("invalid config file {}", path.display()))?;
+    let version = file_version(doc.as_table()).with_context(|| path.display().to_string())?;
+    let current = schema.current_version();
+
+    if version == current {
+        return Ok(content.to_string());
+    }
+    if version > current {
+        return Err(anyhow::anyhow!(
+            "{}: version {} is newer than supported version {}, please update gpt-shell",
+            path.display(),
+            version,
+            current
+        ));
+    }
+
+    for migration in &schema.migrations()[version as usize..] {
+        migration(&mut doc).with_context(|| format!("failed to migrate {}", path.display()))?;
+    }
+    doc.insert("version", value(current as i64));
+    // version 放在文件开头
+    doc.as_table_mut()
+        .sort_values_by(|k1, _, k2, _| (k2.get() == "version").cmp(&(k1.get() == "version")));
+    let upgraded = doc.to_string();
+
+    if persist {
+        let mut backup = path.as_os_str().to_owned();
+        backup.push(format!(".v{}.bak", version));
+        fs::copy(path, &backup)
+            .with_context(|| format!("failed to back up {}", path.display()))?;
+        utils::save_file(&upgraded, &path.to_path_buf())?;
+        eprintln!(
+            "upgraded {} from version {} to {} (backup: {})",
+            path.display(),
+            version,
+            current,
+            Path::new(&backup).display()
+        );
+    }
+    Ok(upgraded)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn upgrade_bots(content: &str) -> String {
+        upgrade(Schema::Bots, Path::new("bots.toml"), content, false).unwrap()
+    }
+
+    #[test]
+    fn bots_v0_gets_bots_table_and_version() {
+        let upgraded = upgrade_bots("current = \"\"\n");
+        assert!(upgraded.starts_with("version = 1\n"), "{}", upgraded);
+        let parsed: toml::Value = toml::from_str(&upgraded).unwrap();
+        assert!(parsed["bots"].is_table());
+    }
+
+    #[test]
+    fn bots_v0_prompts_are_escaped_as_literal_text() {
+        let upgraded = upgrade_bots(
+            "[bots.json]\n# keep me\nsystem_prompt = \"Reply as {{\\\"a\\\": 1}}\" # trailing\n\n[bots.plain]\nsystem_prompt = \"hello\"\n",
+        );
+        assert!(upgraded.contains("# keep me"));
+        assert!(upgraded.contains("# trailing"));
+        let parsed: toml::Value = toml::from_str(&upgraded).unwrap();
+        assert_eq!(parsed["bots"]["json"]["system_prompt"].as_str(), Some("Reply as \\{{\"a\": 1}}"));
+        assert_eq!(parsed["bots"]["plain"]["system_prompt"].as_str(), Some("hello"));
+
+        let rendered = crate::template::render(
+            parsed["bots"]["json"]["system_prompt"].as_str().unwrap(),
+            &Default::default(),
+        )
+        .unwrap();
+        assert_eq!(rendered, "Reply as {{\"a\": 1}}");
+    }
+
+    #[test]
+    fn current_version_is_unchanged() {
+        let content = "version = 1\n[bots.a]\nsystem_prompt = \"{{lang}}\"\n";
+        assert_eq!(upgrade_bots(content), content);
+    }
+
+    #[test]
+    fn newer_version_is_rejected() {
+        let error = upgrade(Schema::Config, Path::new("config.toml"), "version = 99\n", false).unwrap_err();
+        assert!(error.to_string().contains("newer than supported"), "{}", error);
+    }
+
+    #[test]
+    fn invalid_version_is_rejected() {
+        assert!(upgrade(Schema::Agent, Path::new("a.toml"), "version = \"x\"\n", false).is_err());
+    }
+}
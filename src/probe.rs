@@ -0,0 +1,289 @@
+use crate::config::Config;
+use crate::llm_provider::{ChatEvent, FinishReason, FunctionDef, GenerationParams, LLMProvider, Message, Provider, ProviderError};
+use crate::utils;
+use anyhow::Result;
+use colored::*;
+use futures::StreamExt;
+use std::sync::atomic::AtomicBool;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// 单个请求的超时时间
+const TIMEOUT: Duration = Duration::from_secs(30);
+
+/// 连通性与 JSON 检查的最大生成长度，避免消耗过多额度
+/// 推理模型会先用掉一部分思考 token，上限过低时拿不到回答；流式与工具检查不限制长度
+const MAX_TOKENS: u32 = 1024;
+
+/// 请求失败的原因分类
+struct Failure {
+    kind: &'static str,
+    message: String,
+}
+
+/// 功能支持情况
+enum Support {
+    Yes,
+    No(String),
+    Failed(Failure),
+    Skipped,
+}
+
+impl Support {
+    fn cell(&self) -> (String, Option<Color>) {
+        match self {
+            Support::Yes => ("yes".to_string(), Some(Color::Green)),
+            Support::No(_) => ("no".to_string(), Some(Color::Yellow)),
+            Support::Failed(_) => ("error".to_string(), Some(Color::Red)),
+            Support::Skipped => ("-".to_string(), None),
+        }
+    }
+
+    fn note(&self) -> Option<String> {
+        match self {
+            Support::No(reason) => Some(format!("unsupported: {}", reason)),
+            Support::Failed(failure) => Some(format!("{}: {}", failure.kind, failure.message)),
+            _ => None,
+        }
+    }
+}
+
+struct ProbeResult {
+    name: String,
+    status: Option<Failure>,
+    latency: Option<Duration>,
+    ttft: Option<Duration>,
+    stream: Support,
+    json: Support,
+    tools: Support,
+}
+
+struct Response {
+    text: String,
+    /// 回复中包含工具调用
+    tool_called: bool,
+    latency: Duration,
+    ttft: Option<Duration>,
+}
+
+/// 发送一条用户消息并读取完整回复，记录总耗时与首个 token 的耗时
+async fn send(provider: &Provider, prompt: &str, stream: bool, running: Arc<AtomicBool>) -> Result<Response> {
+    let start = Instant::now();
+    let request = async {
+        let messages = vec![Message::new("user", prompt.to_string())];
+        let mut chunks = provider.chat(messages, stream, running).await?;
+        let mut text = String::new();
+        let mut tool_called = false;
+        let mut ttft = None;
+        while let Some(event) = chunks.next().await {
+            match event {
+                ChatEvent::TextDelta(chunk) => {
+                    ttft.get_or_insert_with(|| start.elapsed());
+                    text.push_str(&chunk);
+                }
+                // 推理模型先输出思考过程，同样算作首个 token
+                ChatEvent::ReasoningDelta(_) => {
+                    ttft.get_or_insert_with(|| start.elapsed());
+                }
+                ChatEvent::ToolCallDelta(_) => {
+                    ttft.get_or_insert_with(|| start.elapsed());
+                    tool_called = true;
+                }
+                ChatEvent::Finish(FinishReason::ToolCalls) => tool_called = true,
+                ChatEvent::Error(e) => return Err(e.into()),
+                ChatEvent::Usage(_) | ChatEvent::Finish(_) => {}
+            }
+        }
+        Ok(Response {
+            text,
+            tool_called,
+            latency: start.elapsed(),
+            ttft,
+        })
+    };
+    tokio::time::timeout(TIMEOUT, request)
+        .await
+        .map_err(|_| anyhow::anyhow!("timed out after {}s", TIMEOUT.as_secs()))?
+}
+
+fn classify(e: &anyhow::Error) -> Failure {
+    let Some(error) = e.downcast_ref::<ProviderError>() else {
+        let message = e.to_string();
+        let kind = if message.starts_with("timed out") { "timeout" } else { "error" };
+        return Failure { kind, message };
+    };
+    let lower = error.message().to_lowercase();
+    // 细分网络与地址问题，便于定位配置错误
+    let kind = match error {
+        ProviderError::Network { .. } if lower.contains("certificate") || lower.contains("tls") || lower.contains("ssl") => "tls",
+        ProviderError::Network { .. } if lower.contains("timed out") => "timeout",
+        ProviderError::BadRequest { status: Some(404), .. } => "url",
+        ProviderError::BadRequest { status: None, .. } if lower.starts_with("invalid api_url") => "url",
+        _ => error.kind(),
+    };
+    Failure {
+        kind,
+        message: error.to_string(),
+    }
+}
+
+/// 功能请求失败时区分为不支持（服务端拒绝请求参数）与其他错误
+fn feature_failure(e: &anyhow::Error) -> Support {
+    match e.downcast_ref::<ProviderError>() {
+        Some(ProviderError::BadRequest { message, .. }) => Support::No(message.clone()),
+        _ => Support::Failed(classify(e)),
+    }
+}
+
+async fn probe(name: String, provider: Result<Provider>, params: GenerationParams, running: Arc<AtomicBool>) -> ProbeResult {
+    let mut result = ProbeResult {
+        name,
+        status: None,
+        latency: None,
+        ttft: None,
+        stream: Support::Skipped,
+        json: Support::Skipped,
+        tools: Support::Skipped,
+    };
+    // 证书或代理设置有误时无法创建客户端
+    let provider = match provider {
+        Ok(provider) => provider,
+        Err(e) => {
+            result.status = Some(Failure {
+                kind: "config",
+                message: format!("{:#}", e),
+            });
+            return result;
+        }
+    };
+
+    let capped = provider.clone().with_params(params.merge(&GenerationParams {
+        max_tokens: Some(MAX_TOKENS),
+        ..Default::default()
+    }));
+
+    match send(&capped, "Reply with OK.", false, running.clone()).await {
+        Ok(response) => result.latency = Some(response.latency),
+        Err(e) => {
+            result.status = Some(classify(&e));
+            return result;
+        }
+    }
+
+    result.stream = match send(&provider, "Count from 1 to 5.", true, running.clone()).await {
+        Ok(response) if response.text.is_empty() => Support::No("no content was streamed".to_string()),
+        Ok(response) => {
+            result.ttft = response.ttft;
+            Support::Yes
+        }
+        Err(e) => feature_failure(&e),
+    };
+
+    let json_provider = capped.with_json_mode(true);
+    result.json = match send(&json_provider, "Return {\"ok\": true} as a JSON object.", false, running.clone()).await {
+        Ok(response) if serde_json::from_str::<serde_json::Value>(response.text.trim()).is_ok() => Support::Yes,
+        Ok(_) => Support::No("response is not valid JSON".to_string()),
+        Err(e) => feature_failure(&e),
+    };
+
+    let tools_provider = provider.clone().with_functions(vec![FunctionDef {
+        name: "get_current_time".to_string(),
+        description: "Get the current time".to_string(),
+        parameters: serde_json::json!({"type": "object", "properties": {}}),
+    }]);
+    result.tools = match send(&tools_provider, "What time is it? Use the tool.", false, running).await {
+        Ok(response) if response.tool_called => Support::Yes,
+        Ok(_) => Support::No("model answered without calling the tool".to_string()),
+        Err(e) => feature_failure(&e),
+    };
+    result
+}
+
+fn format_duration(duration: Option<Duration>) -> String {
+    duration
+        .map(|d| format!("{}ms", d.as_millis()))
+        .unwrap_or_else(|| "-".to_string())
+}
+
+fn print_table(results: &[ProbeResult]) {
+    let header = ["MODEL", "STATUS", "LATENCY", "TTFT", "STREAM", "JSON", "TOOLS"];
+    let rows: Vec<Vec<(String, Option<Color>)>> = results
+        .iter()
+        .map(|result| {
+            let status = match &result.status {
+                None => ("ok".to_string(), Some(Color::Green)),
+                Some(failure) => (failure.kind.to_string(), Some(Color::Red)),
+            };
+            vec![
+                (result.name.clone(), None),
+                status,
+                (format_duration(result.latency), None),
+                (format_duration(result.ttft), None),
+                result.stream.cell(),
+                result.json.cell(),
+                result.tools.cell(),
+            ]
+        })
+        .collect();
+
+    utils::print_table(&header, &rows);
+
+    for result in results {
+        let notes: Vec<String> = [
+            ("status", result.status.as_ref().map(|f| format!("{}: {}", f.kind, f.message))),
+            ("stream", result.stream.note()),
+            ("json", result.json.note()),
+            ("tools", result.tools.note()),
+        ]
+        .into_iter()
+        .filter_map(|(field, note)| note.map(|note| format!("{} {}", field, note)))
+        .collect();
+        if !notes.is_empty() {
+            println!();
+            println!("{}:", result.name.green());
+            for note in notes {
+                println!("  {}", note);
+            }
+        }
+    }
+}
+
+/// 检查模型的连通性、密钥和功能支持情况
+/// 未指定名称时检查当前模型，all 为 true 时检查所有模型
+pub async fn run(config: &Config, name: Option<&str>, all: bool, running: Arc<AtomicBool>) -> Result<()> {
+    let names: Vec<String> = if all {
+        config.model_names()
+    } else {
+        match name {
+            Some(name) => vec![name.to_string()],
+            None => vec![config
+                .get_current_model()
+                .map(|(name, _)| name.to_string())
+                .ok_or_else(|| anyhow::anyhow!("no model configured"))?],
+        }
+    };
+    if names.is_empty() {
+        println!("no models configured yet");
+        return Ok(());
+    }
+
+    let mut probes = Vec::new();
+    for name in names {
+        let model = config
+            .get_model(&name)
+            .cloned()
+            .ok_or_else(|| anyhow::anyhow!("model not found: {}", name))?;
+        // 测试需要真实的请求，不使用响应缓存
+        let provider = config.provider(&model).map(|provider| provider.with_cache(None));
+        probes.push(probe(name, provider, model.params.clone(), running.clone()));
+    }
+    println!("testing {} model(s)...", probes.len());
+    let results = futures::future::join_all(probes).await;
+    print_table(&results);
+
+    let failed = results.iter().filter(|result| result.status.is_some()).count();
+    if failed > 0 {
+        return Err(anyhow::anyhow!("{} of {} model(s) failed", failed, results.len()));
+    }
+    Ok(())
+}
@@ -0,0 +1,209 @@
+use crate::agents::{Agent, AgentManager};
+use crate::bots::Bot;
+use crate::config::ModelConfig;
+use crate::utils;
+use anyhow::{Context, Result};
+use colored::*;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+
+/// 项目级配置
+/// 位于仓库中的 `.gpt-shell/` 目录或 `gpt-shell.toml` 文件，需要先用 `gpt project trust` 信任才会加载
+/// 其中的模型、机器人、agent 和系统提示词叠加在用户级配置之上，不会写回用户配置文件，
+/// 也不能覆盖用户定义的同名模型、机器人、别名和 agent
+#[derive(Debug, Default, Clone, Deserialize)]
+pub struct ProjectConfig {
+    /// 项目配置所在路径
+    #[serde(skip)]
+    pub path: PathBuf,
+
+    #[serde(default)]
+    pub models: HashMap<String, ModelConfig>,
+
+    #[serde(default)]
+    pub current_model: Option<String>,
+
+    #[serde(default)]
+    pub system_prompt: Option<String>,
+
+    #[serde(default)]
+    pub bots: HashMap<String, Bot>,
+
+    #[serde(default)]
+    pub aliases: HashMap<String, String>,
+
+    #[serde(default)]
+    pub agents: HashMap<String, Agent>,
+}
+
+impl ProjectConfig {
+    /// 查找并加载当前目录所属项目的配置，每个进程只查找一次
+    /// 项目配置可以修改模型地址并提供会执行命令的 agent，未信任的项目配置只提示不加载
+    /// 项目配置无法解析时只输出警告并忽略项目层，避免无关目录中的错误配置导致所有命令失败
+    pub fn discover() -> Option<Self> {
+        static PROJECT: OnceLock<Option<ProjectConfig>> = OnceLock::new();
+        PROJECT
+            .get_or_init(|| {
+                let path = utils::find_project_config()?;
+                if !TrustedProjects::load().is_ok_and(|trusted| trusted.contains(&path)) {
+                    eprintln!(
+                        "{} ignoring untrusted project config {}, review it and run `gpt project trust` to use it",
+                        "note:".yellow(),
+                        path.display()
+                    );
+                    return None;
+                }
+                match Self::load_from(&path) {
+                    Ok(project) => {
+                        eprintln!("{}", format!("using project config {}", path.display()).dimmed());
+                        Some(project)
+                    }
+                    Err(e) => {
+                        eprintln!(
+                            "{} ignoring project config {}: {:#}",
+                            "warning:".yellow(),
+                            path.display(),
+                            e
+                        );
+                        None
+                    }
+                }
+            })
+            .clone()
+    }
+
+    /// 从 `gpt-shell.toml` 文件或 `.gpt-shell/` 目录加载
+    /// 目录形式与用户配置目录结构一致：config.toml、bots.toml 与 agents/*.toml
+    pub fn load_from(path: &Path) -> Result<Self> {
+        let mut project = if path.is_dir() {
+            let mut project = Self::default();
+            for file in ["config.toml", "bots.toml"] {
+                let file_path = path.join(file);
+                if file_path.exists() {
+                    project.extend(Self::parse_file(&file_path)?);
+                }
+            }
+            let mut agents = AgentManager::new();
+            agents.load_dir(&path.join("agents"))?;
+            project.agents.extend(agents.agents);
+            project
+        } else {
+            Self::parse_file(path)?
+        };
+        project.path = path.to_path_buf();
+        Ok(project)
+    }
+
+    fn parse_file(path: &Path) -> Result<Self> {
+        let content = fs::read_to_string(path)?;
+        toml::from_str(&content)
+            .with_context(|| format!("invalid project config: {}", path.display()))
+    }
+
+    fn extend(&mut self, other: Self) {
+        self.models.extend(other.models);
+        self.bots.extend(other.bots);
+        self.aliases.extend(other.aliases);
+        self.agents.extend(other.agents);
+        if other.current_model.is_some() {
+            self.current_model = other.current_model;
+        }
+        if other.system_prompt.is_some() {
+            self.system_prompt = other.system_prompt;
+        }
+    }
+}
+
+/// 移除与用户配置同名的项目条目并输出警告
+/// 项目配置只能新增，不能替换用户已经在使用的模型、机器人、别名或 agent
+pub fn drop_shadowed<T>(
+    project: &ProjectConfig,
+    kind: &str,
+    entries: &mut HashMap<String, T>,
+    user_defined: impl Fn(&str) -> bool,
+) {
+    entries.retain(|name, _| {
+        if !user_defined(name) {
+            return true;
+        }
+        eprintln!(
+            "{} {} {} from project config {} is ignored, a user {} with the same name exists",
+            "warning:".yellow(),
+            kind,
+            name,
+            project.path.display(),
+            kind
+        );
+        false
+    });
+}
+
+/// 已信任的项目配置路径，保存在用户配置目录的 trusted_projects.toml
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct TrustedProjects {
+    #[serde(default)]
+    pub paths: Vec<PathBuf>,
+}
+
+impl TrustedProjects {
+    pub fn get_path() -> Option<PathBuf> {
+        Some(utils::get_config_dir()?.join("trusted_projects.toml"))
+    }
+
+    pub fn load() -> Result<Self> {
+        match Self::get_path() {
+            Some(path) if path.exists() => {
+                let content = fs::read_to_string(&path)?;
+                toml::from_str(&content).with_context(|| format!("invalid trusted projects file: {}", path.display()))
+            }
+            _ => Ok(Self::default()),
+        }
+    }
+
+    /// 按规范化后的路径比较，符号链接或相对路径指向同一位置时视为同一个项目
+    pub fn contains(&self, path: &Path) -> bool {
+        let path = canonical(path);
+        self.paths.iter().any(|trusted| trusted == &path)
+    }
+
+    /// 信任或取消信任项目配置，在文件锁内读取、修改并保存
+    pub fn set(path: &Path, trusted: bool) -> Result<()> {
+        let file = Self::get_path().ok_or_else(|| anyhow::anyhow!("can not find config directory"))?;
+        let _lock = utils::lock_file(&file)?;
+        let mut projects = Self::load()?;
+        let path = canonical(path);
+        projects.paths.retain(|existing| existing != &path);
+        if trusted {
+            projects.paths.push(path.clone());
+        }
+        utils::save_file(&toml::to_string_pretty(&projects)?, &file)?;
+        if trusted {
+            println!("trusted project config: {}", path.display().to_string().green());
+        } else {
+            println!("untrusted project config: {}", path.display());
+        }
+        Ok(())
+    }
+
+    /// 输出当前目录所属的项目配置及其信任状态
+    pub fn print_status() -> Result<()> {
+        let Some(path) = utils::find_project_config() else {
+            println!("no project config found from the current directory");
+            return Ok(());
+        };
+        let status = if Self::load()?.contains(&path) {
+            "trusted".green()
+        } else {
+            "not trusted, run `gpt project trust` to use it".yellow()
+        };
+        println!("project config: {} ({})", path.display(), status);
+        Ok(())
+    }
+}
+
+fn canonical(path: &Path) -> PathBuf {
+    fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf())
+}
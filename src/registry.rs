@@ -0,0 +1,383 @@
+use crate::agents::{Agent, AgentManager};
+use crate::bots::{Bot, BotsConfig};
+use crate::utils;
+use anyhow::{Context, Result};
+use colored::*;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+/// 共享的机器人与 agent 来源
+/// 目录结构与用户配置目录一致：bots.toml 与 agents/*.toml
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Registry {
+    /// 本地目录或 git 仓库地址
+    pub source: String,
+    /// 固定的 git 版本（tag、分支或提交），仅对 git 来源有效
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pin: Option<String>,
+    /// 最近一次同步到的提交
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub revision: Option<String>,
+}
+
+impl Registry {
+    fn is_git(&self) -> bool {
+        !Path::new(&self.source).is_dir()
+            && (self.source.contains("://")
+                || self.source.starts_with("git@")
+                || self.source.ends_with(".git"))
+    }
+
+    /// registry 内容所在目录：本地来源直接读取，git 来源克隆到配置目录下
+    fn dir(&self, name: &str) -> Result<PathBuf> {
+        if self.is_git() {
+            let mut path = RegistryConfig::get_checkouts_dir()?;
+            path.push(name);
+            Ok(path)
+        } else {
+            Ok(PathBuf::from(&self.source))
+        }
+    }
+
+    fn sync(&mut self, name: &str) -> Result<()> {
+        let dir = self.dir(name)?;
+        if !self.is_git() {
+            if !dir.is_dir() {
+                return Err(anyhow::anyhow!("registry directory not found: {}", dir.display()));
+            }
+            if self.pin.is_some() {
+                return Err(anyhow::anyhow!("pinning requires a git source: {}", self.source));
+            }
+            return Ok(());
+        }
+
+        if dir.join(".git").exists() {
+            git(&dir, &["fetch", "--tags", "--force", "origin"])?;
+        } else {
+            if let Some(parent) = dir.parent() {
+                fs::create_dir_all(parent)?;
+            }
+            // `--` 之后的来源不会被当作 git 参数
+            let output = Command::new("git")
+                .args(["clone", "--quiet", "--"])
+                .arg(&self.source)
+                .arg(&dir)
+                .output()
+                .context("failed to run git, is it installed?")?;
+            if !output.status.success() {
+                return Err(anyhow::anyhow!(
+                    "git clone failed: {}",
+                    String::from_utf8_lossy(&output.stderr).trim()
+                ));
+            }
+        }
+        let target = match self.pin.as_deref() {
+            None => "origin/HEAD".to_string(),
+            Some(pin) if pin.starts_with('-') => {
+                return Err(anyhow::anyhow!("invalid pin: {}", pin));
+            }
+            // 克隆时创建的本地分支不会随 fetch 更新，分支需要使用远端分支，否则按 tag 或提交处理
+            Some(pin) => {
+                let remote = format!("origin/{}", pin);
+                match git(&dir, &["rev-parse", "--verify", "--quiet", &format!("{}^{{commit}}", remote)]) {
+                    Ok(_) => remote,
+                    Err(_) => pin.to_string(),
+                }
+            }
+        };
+        git(&dir, &["checkout", "--quiet", "--detach", &target])?;
+        self.revision = Some(git(&dir, &["rev-parse", "--short", "HEAD"])?);
+        Ok(())
+    }
+}
+
+fn git(dir: &Path, args: &[&str]) -> Result<String> {
+    let output = Command::new("git")
+        .arg("-C")
+        .arg(dir)
+        .args(args)
+        .output()
+        .context("failed to run git, is it installed?")?;
+    if !output.status.success() {
+        return Err(anyhow::anyhow!(
+            "git {} failed: {}",
+            args.join(" "),
+            String::from_utf8_lossy(&output.stderr).trim()
+        ));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct RegistryConfig {
+    #[serde(default)]
+    pub registries: HashMap<String, Registry>,
+}
+
+impl RegistryConfig {
+    pub fn load() -> Result<Self> {
+        match Self::get_path() {
+            Some(path) if path.exists() => {
+                let content = fs::read_to_string(&path)?;
+                toml::from_str(&content)
+                    .with_context(|| format!("invalid registry config: {}", path.display()))
+            }
+            _ => Ok(RegistryConfig::default()),
+        }
+    }
+
+    pub fn save(&self) -> Result<()> {
+        if let Some(path) = Self::get_path() {
+            let content = toml::to_string_pretty(self)?;
+            utils::save_file(&content, &path)?;
+        }
+        Ok(())
+    }
+
+    pub fn get_path() -> Option<PathBuf> {
+        let mut path = utils::get_config_dir()?;
+        path.push("registries.toml");
+        Some(path)
+    }
+
+    fn get_checkouts_dir() -> Result<PathBuf> {
+        utils::get_config_dir()
+            .map(|mut path| {
+                path.push("registries");
+                path
+            })
+            .ok_or_else(|| anyhow::anyhow!("can not find config directory"))
+    }
+
+    pub fn add(&mut self, name: Option<String>, source: String, pin: Option<String>) -> Result<()> {
+        let name = match name {
+            Some(name) => name,
+            None => default_name(&source)?,
+        };
+        // 名称会作为 `<registry>/<bot>` 的前缀和缓存目录名，与机器人别名使用相同的字符集
+        if name.is_empty() || !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_') {
+            return Err(anyhow::anyhow!(
+                "invalid registry name: '{}', it may only contain letters, digits, '-' and '_', use --name to choose one",
+                name
+            ));
+        }
+        if self.registries.contains_key(&name) {
+            return Err(anyhow::anyhow!(
+                "registry already exists: {}, use `gpt registry update {}` instead",
+                name,
+                name
+            ));
+        }
+
+        // 本地目录保存为绝对路径，避免依赖当前工作目录
+        let source = match fs::canonicalize(&source) {
+            Ok(path) if path.is_dir() => path.display().to_string(),
+            _ => source,
+        };
+        let mut registry = Registry {
+            source,
+            pin,
+            revision: None,
+        };
+        registry.sync(&name)?;
+        self.report(&name, &registry)?;
+        self.registries.insert(name.clone(), registry);
+        self.save()?;
+        println!("registry added: {}", name.green());
+        Ok(())
+    }
+
+    pub fn remove(&mut self, name: &str) -> Result<()> {
+        let registry = self
+            .registries
+            .remove(name)
+            .ok_or_else(|| anyhow::anyhow!("registry not found: {}", name))?;
+        if registry.is_git() {
+            let dir = registry.dir(name)?;
+            if dir.exists() {
+                fs::remove_dir_all(dir)?;
+            }
+        }
+        self.save()?;
+        println!("registry removed: {}", name.green());
+        Ok(())
+    }
+
+    /// 同步指定 registry，未指定时同步全部
+    pub fn update(&mut self, name: Option<&str>) -> Result<()> {
+        let names: Vec<String> = match name {
+            Some(name) if self.registries.contains_key(name) => vec![name.to_string()],
+            Some(name) => return Err(anyhow::anyhow!("registry not found: {}", name)),
+            None => self.registries.keys().cloned().collect(),
+        };
+        if names.is_empty() {
+            println!("no registries added yet");
+            return Ok(());
+        }
+
+        let mut failed = 0;
+        for name in names {
+            let mut registry = self.registries[&name].clone();
+            match registry.sync(&name) {
+                Ok(()) => {
+                    self.report(&name, &registry)?;
+                    self.registries.insert(name, registry);
+                }
+                Err(e) => {
+                    eprintln!("{} {}: {}", "failed to update".red(), name, e);
+                    failed += 1;
+                }
+            }
+        }
+        self.save()?;
+        if failed > 0 {
+            return Err(anyhow::anyhow!("{} registry update(s) failed", failed));
+        }
+        Ok(())
+    }
+
+    /// 固定或取消固定 git 版本，并立即同步
+    pub fn pin(&mut self, name: &str, pin: Option<String>) -> Result<()> {
+        let mut registry = self
+            .registries
+            .get(name)
+            .cloned()
+            .ok_or_else(|| anyhow::anyhow!("registry not found: {}", name))?;
+        registry.pin = pin;
+        registry.sync(name)?;
+        match &registry.pin {
+            Some(pin) => println!("registry {} pinned to {}", name.green(), pin.cyan()),
+            None => println!("registry {} unpinned", name.green()),
+        }
+        self.registries.insert(name.to_string(), registry);
+        self.save()
+    }
+
+    pub fn list(&self) {
+        if self.registries.is_empty() {
+            println!("no registries added yet");
+            return;
+        }
+
+        println!("registries:");
+        for (name, registry) in &self.registries {
+            println!("- {}", name.green());
+            println!("  source: {}", registry.source);
+            if let Some(pin) = &registry.pin {
+                println!("  pin: {}", pin.cyan());
+            }
+            if let Some(revision) = &registry.revision {
+                println!("  revision: {}", revision);
+            }
+        }
+    }
+
+    fn report(&self, name: &str, registry: &Registry) -> Result<()> {
+        let dir = registry.dir(name)?;
+        let bots = load_registry_bots(&dir)?;
+        let mut agents = AgentManager::new();
+        agents.load_dir(&dir.join("agents"))?;
+        println!(
+            "synced {}: {} bot(s), {} agent(s){}",
+            name.green(),
+            bots.len(),
+            agents.agents.len(),
+            registry
+                .revision
+                .as_ref()
+                .map(|r| format!(" at {}", r))
+                .unwrap_or_default()
+        );
+        Ok(())
+    }
+
+    /// 加载所有 registry 中的内容，registry 配置或单个 registry 出错时输出警告并跳过
+    /// 避免一个损坏的 registry 导致所有命令失败，包括用于修复的 `gpt registry remove`
+    fn load_each<T>(load: impl Fn(&RegistryConfig) -> Result<HashMap<String, T>>) -> HashMap<String, T> {
+        let config = match Self::load() {
+            Ok(config) => config,
+            Err(e) => {
+                eprintln!("{} {:#}", "warning:".yellow(), e);
+                return HashMap::new();
+            }
+        };
+        let mut items = HashMap::new();
+        for (name, registry) in config.registries {
+            let mut single = RegistryConfig::default();
+            single.registries.insert(name.clone(), registry);
+            match load(&single) {
+                Ok(loaded) => items.extend(loaded),
+                Err(e) => eprintln!(
+                    "{} {:#}\n  skipped, fix it or run `gpt registry remove {}`",
+                    "warning:".yellow(),
+                    e,
+                    name
+                ),
+            }
+        }
+        items
+    }
+
+    /// 所有可用 registry 中的机器人，出错的 registry 会被跳过
+    pub fn available_bots() -> HashMap<String, Bot> {
+        Self::load_each(Self::load_bots)
+    }
+
+    /// 所有可用 registry 中的 agent，出错的 registry 会被跳过
+    pub fn available_agents() -> HashMap<String, Agent> {
+        Self::load_each(Self::load_agents)
+    }
+
+    /// 所有 registry 中的机器人，名称为 `<registry>/<bot>`
+    pub fn load_bots(&self) -> Result<HashMap<String, Bot>> {
+        let mut bots = HashMap::new();
+        for (name, registry) in &self.registries {
+            let dir = registry.dir(name)?;
+            for (bot_name, mut bot) in load_registry_bots(&dir)
+                .with_context(|| format!("registry {}", name))?
+            {
+                let full_name = format!("{}/{}", name, bot_name);
+                bot.name = full_name.clone();
+                bots.insert(full_name, bot);
+            }
+        }
+        Ok(bots)
+    }
+
+    /// 所有 registry 中的 agent，名称为 `<registry>/<agent>`
+    pub fn load_agents(&self) -> Result<HashMap<String, Agent>> {
+        let mut agents = HashMap::new();
+        for (name, registry) in &self.registries {
+            let mut manager = AgentManager::new();
+            manager
+                .load_dir(&registry.dir(name)?.join("agents"))
+                .with_context(|| format!("registry {}", name))?;
+            for (agent_name, agent) in manager.agents {
+                agents.insert(format!("{}/{}", name, agent_name), agent);
+            }
+        }
+        Ok(agents)
+    }
+}
+
+fn load_registry_bots(dir: &Path) -> Result<HashMap<String, Bot>> {
+    let path = dir.join("bots.toml");
+    if !path.exists() {
+        return Ok(HashMap::new());
+    }
+    Ok(BotsConfig::load_from(&path)?.bots)
+}
+
+/// 由来源推断 registry 名称，例如 https://host/org/team-bots.git -> team-bots
+fn default_name(source: &str) -> Result<String> {
+    source
+        .trim_end_matches('/')
+        .rsplit(['/', '\\', ':'])
+        .next()
+        .map(|name| name.trim_end_matches(".git").to_string())
+        .filter(|name| !name.is_empty())
+        .ok_or_else(|| anyhow::anyhow!("can not infer registry name from {}, use --name", source))
+}
@@ -0,0 +1,345 @@
+use crate::llm_provider::ProviderError;
+use anyhow::{Context, Result};
+use serde_json::Value;
+use std::fs;
+use std::path::Path;
+
+/// 结构化输出使用的 JSON Schema
+/// 本地校验支持常用的关键字：type、enum、const、properties、required、additionalProperties、
+/// items、长度与数值范围、anyOf/oneOf/allOf 以及指向 $defs/definitions 的 $ref，其他关键字会被忽略
+#[derive(Debug, Clone, PartialEq)]
+pub struct OutputSchema {
+    /// 发送给服务端的 schema 名称，只包含字母、数字、下划线和连字符
+    pub name: String,
+    pub schema: Value,
+}
+
+impl OutputSchema {
+    pub fn new(name: &str, schema: Value) -> Result<Self> {
+        if !schema.is_object() {
+            return Err(anyhow::anyhow!("schema must be a JSON object"));
+        }
+        let name: String = name
+            .chars()
+            .map(|c| if c.is_ascii_alphanumeric() || c == '_' || c == '-' { c } else { '_' })
+            .take(64)
+            .collect();
+        Ok(Self {
+            name: if name.is_empty() { "output".to_string() } else { name },
+            schema,
+        })
+    }
+
+    /// 从 JSON 文件读取 schema，文件名作为 schema 名称
+    pub fn load(path: &Path) -> Result<Self> {
+        let content = fs::read_to_string(path)
+            .with_context(|| format!("can not read schema file {}", path.display()))?;
+        let schema: Value = serde_json::from_str(&content)
+            .with_context(|| format!("invalid JSON in schema file {}", path.display()))?;
+        let name = path.file_stem().and_then(|stem| stem.to_str()).unwrap_or_default();
+        Self::new(name, schema).with_context(|| path.display().to_string())
+    }
+
+    /// OpenAI 格式的 response_format 参数
+    pub fn response_format(&self) -> Value {
+        serde_json::json!({
+            "type": "json_schema",
+            "json_schema": {
+                "name": self.name,
+                "schema": self.schema,
+            }
+        })
+    }
+
+    /// 校验 JSON 值，返回所有不匹配的位置与原因
+    pub fn validate(&self, value: &Value) -> Vec<String> {
+        let mut errors = Vec::new();
+        check(&self.schema, &self.schema, value, "$", &[], &mut errors);
+        errors
+    }
+}
+
+/// 服务端是否因为不支持 json_schema 而拒绝请求
+/// 只看错误内容是否提到 response_format 或 json_schema，地址或其他参数错误不算
+pub fn is_unsupported(error: &anyhow::Error) -> bool {
+    match error.downcast_ref::<ProviderError>() {
+        Some(ProviderError::BadRequest { status, message }) if *status != Some(404) => {
+            let message = message.to_lowercase();
+            message.contains("response_format") || message.contains("json_schema")
+        }
+        _ => false,
+    }
+}
+
+/// 从模型回复中解析 JSON，兼容包裹在 ``` 代码块中的回复
+pub fn parse_reply(reply: &str) -> Result<Value, serde_json::Error> {
+    let trimmed = reply.trim();
+    let unfenced = trimmed
+        .strip_prefix("```")
+        .and_then(|rest| rest.strip_suffix("```"))
+        .map(|body| body.trim_start_matches("json").trim())
+        .unwrap_or(trimmed);
+    serde_json::from_str(unfenced)
+}
+
+/// 解析 "#/$defs/name" 形式的本地引用
+fn resolve<'a>(root: &'a Value, reference: &str) -> Option<&'a Value> {
+    let pointer = reference.strip_prefix('#')?;
+    root.pointer(pointer)
+}
+
+fn type_name(value: &Value) -> &'static str {
+    match value {
+        Value::Null => "null",
+        Value::Bool(_) => "boolean",
+        Value::Number(n) if n.is_i64() || n.is_u64() => "integer",
+        Value::Number(_) => "number",
+        Value::String(_) => "string",
+        Value::Array(_) => "array",
+        Value::Object(_) => "object",
+    }
+}
+
+fn matches_type(value: &Value, expected: &str) -> bool {
+    match expected {
+        // 整数同时也是 number，1.0 也视为整数
+        "number" => value.is_number(),
+        "integer" => value.as_f64().is_some_and(|n| n.fract() == 0.0),
+        other => type_name(value) == other,
+    }
+}
+
+/// refs 为校验同一个值时已经解析过的 $ref，再次遇到说明引用成环，进入子属性或数组元素时清空
+fn check(schema: &Value, root: &Value, value: &Value, path: &str, refs: &[&str], errors: &mut Vec<String>) {
+    let Some(schema) = schema.as_object() else {
+        // true 接受任意值，false 拒绝任意值
+        if schema == &Value::Bool(false) {
+            errors.push(format!("{}: no value is allowed here", path));
+        }
+        return;
+    };
+
+    if let Some(reference) = schema.get("$ref").and_then(Value::as_str) {
+        match resolve(root, reference) {
+            Some(_) if refs.contains(&reference) => {
+                errors.push(format!("{}: $ref '{}' refers to itself", path, reference));
+                return;
+            }
+            Some(target) => {
+                let refs: Vec<&str> = refs.iter().copied().chain([reference]).collect();
+                check(target, root, value, path, &refs, errors);
+            }
+            None => errors.push(format!("{}: can not resolve $ref '{}'", path, reference)),
+        }
+    }
+
+    if let Some(expected) = schema.get("type") {
+        let types: Vec<&str> = match expected {
+            Value::String(name) => vec![name.as_str()],
+            Value::Array(names) => names.iter().filter_map(Value::as_str).collect(),
+            _ => Vec::new(),
+        };
+        if !types.is_empty() && !types.iter().any(|name| matches_type(value, name)) {
+            errors.push(format!("{}: expected {}, found {}", path, types.join(" or "), type_name(value)));
+            return;
+        }
+    }
+
+    if let Some(allowed) = schema.get("enum").and_then(Value::as_array) {
+        if !allowed.contains(value) {
+            let allowed: Vec<String> = allowed.iter().map(Value::to_string).collect();
+            errors.push(format!("{}: {} is not one of {}", path, value, allowed.join(", ")));
+        }
+    }
+    if let Some(expected) = schema.get("const") {
+        if expected != value {
+            errors.push(format!("{}: expected {}, found {}", path, expected, value));
+        }
+    }
+
+    // 返回每个子 schema 的校验错误
+    let branches = |keyword: &str| -> Option<Vec<Vec<String>>> {
+        let options = schema.get(keyword)?.as_array()?;
+        Some(
+            options
+                .iter()
+                .map(|option| {
+                    let mut option_errors = Vec::new();
+                    check(option, root, value, path, refs, &mut option_errors);
+                    option_errors
+                })
+                .collect(),
+        )
+    };
+    if let Some(results) = branches("allOf") {
+        errors.extend(results.into_iter().flatten());
+    }
+    if let Some(results) = branches("anyOf") {
+        if !results.iter().any(Vec::is_empty) {
+            errors.push(format!("{}: does not match any schema in anyOf", path));
+        }
+    }
+    if let Some(results) = branches("oneOf") {
+        let passed = results.iter().filter(|errors| errors.is_empty()).count();
+        if passed != 1 {
+            errors.push(format!("{}: must match exactly one schema in oneOf, matched {}", path, passed));
+        }
+    }
+
+    match value {
+        Value::Object(object) => {
+            let properties = schema.get("properties").and_then(Value::as_object);
+            if let Some(required) = schema.get("required").and_then(Value::as_array) {
+                for key in required.iter().filter_map(Value::as_str) {
+                    if !object.contains_key(key) {
+                        errors.push(format!("{}: missing required property '{}'", path, key));
+                    }
+                }
+            }
+            for (key, item) in object {
+                let item_path = format!("{}.{}", path, key);
+                match properties.and_then(|properties| properties.get(key)) {
+                    Some(property) => check(property, root, item, &item_path, &[], errors),
+                    None => match schema.get("additionalProperties") {
+                        Some(Value::Bool(false)) => {
+                            errors.push(format!("{}: unexpected property '{}'", path, key))
+                        }
+                        Some(additional) => check(additional, root, item, &item_path, &[], errors),
+                        None => {}
+                    },
+                }
+            }
+        }
+        Value::Array(items) => {
+            let count = items.len() as u64;
+            if let Some(min) = schema.get("minItems").and_then(Value::as_u64).filter(|min| count < *min) {
+                errors.push(format!("{}: expected at least {} item(s), found {}", path, min, count));
+            }
+            if let Some(max) = schema.get("maxItems").and_then(Value::as_u64).filter(|max| count > *max) {
+                errors.push(format!("{}: expected at most {} item(s), found {}", path, max, count));
+            }
+            if let Some(item_schema) = schema.get("items") {
+                for (i, item) in items.iter().enumerate() {
+                    check(item_schema, root, item, &format!("{}[{}]", path, i), &[], errors);
+                }
+            }
+        }
+        Value::String(text) => {
+            let length = text.chars().count() as u64;
+            if let Some(min) = schema.get("minLength").and_then(Value::as_u64).filter(|min| length < *min) {
+                errors.push(format!("{}: expected at least {} character(s), found {}", path, min, length));
+            }
+            if let Some(max) = schema.get("maxLength").and_then(Value::as_u64).filter(|max| length > *max) {
+                errors.push(format!("{}: expected at most {} character(s), found {}", path, max, length));
+            }
+        }
+        Value::Number(number) => {
+            let number = number.as_f64().unwrap_or_default();
+            let bound = |keyword: &str| schema.get(keyword).and_then(Value::as_f64);
+            if let Some(min) = bound("minimum").filter(|min| number < *min) {
+                errors.push(format!("{}: {} is less than minimum {}", path, number, min));
+            }
+            if let Some(max) = bound("maximum").filter(|max| number > *max) {
+                errors.push(format!("{}: {} is greater than maximum {}", path, number, max));
+            }
+            if let Some(min) = bound("exclusiveMinimum").filter(|min| number <= *min) {
+                errors.push(format!("{}: {} must be greater than {}", path, number, min));
+            }
+            if let Some(max) = bound("exclusiveMaximum").filter(|max| number >= *max) {
+                errors.push(format!("{}: {} must be less than {}", path, number, max));
+            }
+        }
+        _ => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn errors(schema: Value, value: Value) -> Vec<String> {
+        OutputSchema::new("test", schema).unwrap().validate(&value)
+    }
+
+    #[test]
+    fn reports_type_required_and_extra_properties() {
+        let schema = json!({
+            "type": "object",
+            "properties": {"name": {"type": "string"}, "age": {"type": "integer", "minimum": 0}},
+            "required": ["name"],
+            "additionalProperties": false
+        });
+        assert!(errors(schema.clone(), json!({"name": "a", "age": 3})).is_empty());
+        assert_eq!(
+            errors(schema, json!({"age": -1, "x": 1})),
+            [
+                "$: missing required property 'name'",
+                "$.age: -1 is less than minimum 0",
+                "$: unexpected property 'x'",
+            ]
+        );
+    }
+
+    #[test]
+    fn follows_refs_into_defs() {
+        let schema = json!({
+            "type": "array",
+            "items": {"$ref": "#/$defs/person"},
+            "$defs": {"person": {"type": "object", "required": ["name"]}}
+        });
+        assert!(errors(schema.clone(), json!([{"name": "a"}])).is_empty());
+        assert_eq!(errors(schema, json!([{}])), ["$[0]: missing required property 'name'"]);
+        assert_eq!(
+            errors(json!({"$ref": "#/$defs/missing"}), json!(1)),
+            ["$: can not resolve $ref '#/$defs/missing'"]
+        );
+    }
+
+    #[test]
+    fn recursive_schemas_validate_nested_values() {
+        let schema = json!({
+            "$ref": "#/$defs/node",
+            "$defs": {"node": {
+                "type": "object",
+                "properties": {"children": {"type": "array", "items": {"$ref": "#/$defs/node"}}}
+            }}
+        });
+        let mut tree = json!({});
+        for _ in 0..100 {
+            tree = json!({"children": [tree]});
+        }
+        assert!(errors(schema.clone(), tree).is_empty());
+        assert_eq!(errors(schema, json!({"children": [1]})), ["$.children[0]: expected object, found integer"]);
+    }
+
+    #[test]
+    fn self_referencing_refs_stop() {
+        assert_eq!(errors(json!({"$ref": "#"}), json!(1)), ["$: $ref '#' refers to itself"]);
+        let schema = json!({
+            "$ref": "#/$defs/a",
+            "$defs": {"a": {"$ref": "#/$defs/b"}, "b": {"anyOf": [{"$ref": "#/$defs/a"}]}}
+        });
+        assert!(!errors(schema, json!(1)).is_empty());
+        let schema = json!({"allOf": [{"$ref": "#"}, {"$ref": "#"}, {"$ref": "#"}]});
+        let errors = errors(schema, json!(1));
+        assert!(!errors.is_empty() && errors.iter().all(|error| error.ends_with("refers to itself")));
+    }
+
+    #[test]
+    fn parses_fenced_replies() {
+        assert_eq!(parse_reply("```json\n{\"a\": 1}\n```").unwrap(), json!({"a": 1}));
+        assert_eq!(parse_reply(" [1] ").unwrap(), json!([1]));
+    }
+
+    #[test]
+    fn only_response_format_errors_are_unsupported() {
+        let error = |status, message: &str| -> anyhow::Error {
+            ProviderError::BadRequest { status: Some(status), message: message.into() }.into()
+        };
+        assert!(is_unsupported(&error(400, "response_format.type json_schema is not supported")));
+        assert!(!is_unsupported(&error(400, "temperature must be between 0 and 2")));
+        assert!(!is_unsupported(&error(404, "unknown field json_schema")));
+        assert!(!is_unsupported(&ProviderError::Network { message: "json_schema".into() }.into()));
+    }
+}
@@ -0,0 +1,676 @@
+use crate::bots::{Bot, BotsConfig};
+use crate::config::{Config, ModelConfig};
+use crate::llm_provider::{ChatEvent, ChatStream, GenerationParams, LLMProvider, Message, Provider, ProviderError, Usage};
+use crate::schema::OutputSchema;
+use crate::template::TemplateContext;
+use anyhow::Result;
+use colored::*;
+use futures::StreamExt;
+use hyper::body::{Bytes, HttpBody};
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{header, Body, Method, Request, Response, StatusCode};
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use std::collections::hash_map::RandomState;
+use std::collections::BTreeMap;
+use std::convert::Infallible;
+use std::hash::BuildHasher;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+/// 虚拟模型名称的前缀，`bot:reviewer` 表示使用机器人 reviewer
+const BOT_PREFIX: &str = "bot:";
+
+/// 请求体的大小上限
+const MAX_BODY_SIZE: usize = 16 * 1024 * 1024;
+
+/// 本地服务的设置
+pub struct ServeOptions {
+    pub addr: SocketAddr,
+    /// 主模型请求失败时依次尝试的模型
+    pub fallback: Vec<String>,
+    /// 客户端需要提供的 Bearer token，未设置时启动时随机生成
+    pub token: Option<String>,
+}
+
+/// 随机生成访问令牌，RandomState 的种子取自系统随机数
+fn random_token() -> String {
+    let state = RandomState::new();
+    (0u8..2).map(|i| format!("{:016x}", state.hash_one(i))).collect()
+}
+
+/// Host 头是否指向本机，用于拒绝 DNS rebinding：恶意域名解析到 127.0.0.1 时 Host 仍是该域名
+fn is_loopback_host(host: &str) -> bool {
+    let name = match host.strip_prefix('[') {
+        Some(rest) => rest.split(']').next().unwrap_or_default(),
+        None => host.rsplit_once(':').map_or(host, |(name, _)| name),
+    };
+    name.eq_ignore_ascii_case("localhost") || name.parse::<IpAddr>().is_ok_and(|ip| ip.is_loopback())
+}
+
+/// 客户端发送的消息，content 可以是字符串或 OpenAI 的多段内容
+#[derive(Deserialize)]
+struct ClientMessage {
+    role: String,
+    #[serde(default)]
+    content: serde_json::Value,
+    #[serde(default)]
+    tool_call_id: Option<String>,
+    #[serde(default)]
+    tool_calls: Option<serde_json::Value>,
+}
+
+impl ClientMessage {
+    /// 多段内容只保留文本部分
+    /// 不支持工具调用，包含工具调用的消息返回错误，而不是丢掉其中的内容
+    fn into_message(self) -> Result<Message, String> {
+        let has_tool_calls = self
+            .tool_calls
+            .as_ref()
+            .is_some_and(|calls| !calls.is_null() && calls.as_array().is_none_or(|calls| !calls.is_empty()));
+        if self.role == "tool" || self.tool_call_id.is_some() || has_tool_calls {
+            return Err("tool calls are not supported".to_string());
+        }
+        let content = match self.content {
+            serde_json::Value::String(text) => text,
+            serde_json::Value::Array(parts) => parts
+                .iter()
+                .filter_map(|part| part["text"].as_str())
+                .collect::<Vec<_>>()
+                .join("\n"),
+            _ => String::new(),
+        };
+        Ok(Message::new(&self.role, content))
+    }
+}
+
+#[derive(Deserialize)]
+struct ChatRequest {
+    #[serde(default)]
+    model: Option<String>,
+    messages: Vec<ClientMessage>,
+    #[serde(default)]
+    stream: bool,
+    #[serde(default)]
+    response_format: Option<serde_json::Value>,
+    #[serde(flatten)]
+    params: GenerationParams,
+}
+
+/// 每个模型累计的请求数与 token 用量
+#[derive(Default)]
+struct UsageTotal {
+    requests: u64,
+    failures: u64,
+    prompt_tokens: u64,
+    completion_tokens: u64,
+}
+
+struct State {
+    config: Config,
+    bots: BotsConfig,
+    options: ServeOptions,
+    running: Arc<AtomicBool>,
+    usage: Mutex<BTreeMap<String, UsageTotal>>,
+    next_id: AtomicU64,
+}
+
+/// 按 OpenAI 的格式返回错误
+fn error_response(status: StatusCode, kind: &str, message: &str) -> Response<Body> {
+    let body = serde_json::json!({
+        "error": {"message": message, "type": kind, "code": null}
+    });
+    json_response(status, &body)
+}
+
+fn json_response(status: StatusCode, body: &serde_json::Value) -> Response<Body> {
+    Response::builder()
+        .status(status)
+        .header(header::CONTENT_TYPE, "application/json")
+        .body(Body::from(body.to_string()))
+        .unwrap_or_default()
+}
+
+fn provider_error_response(error: &ProviderError) -> Response<Body> {
+    let status = match error {
+        ProviderError::Auth { status, .. } => status
+            .and_then(|status| StatusCode::from_u16(status).ok())
+            .unwrap_or(StatusCode::UNAUTHORIZED),
+        ProviderError::RateLimit { .. } => StatusCode::TOO_MANY_REQUESTS,
+        ProviderError::ContextLength { .. } | ProviderError::ContentFilter { .. } => StatusCode::BAD_REQUEST,
+        ProviderError::BadRequest { status, .. } => status
+            .and_then(|status| StatusCode::from_u16(status).ok())
+            .unwrap_or(StatusCode::BAD_REQUEST),
+        ProviderError::Server { .. } | ProviderError::Network { .. } | ProviderError::Parse { .. } => StatusCode::BAD_GATEWAY,
+    };
+    error_response(status, error.kind(), &error.to_string())
+}
+
+fn unix_time() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
+}
+
+/// 比较 token 的耗时与内容无关，先取哈希使长度也不影响耗时
+fn constant_time_eq(a: &str, b: &str) -> bool {
+    let (a, b) = (Sha256::digest(a.as_bytes()), Sha256::digest(b.as_bytes()));
+    a.iter().zip(b.iter()).fold(0u8, |diff, (x, y)| diff | (x ^ y)) == 0
+}
+
+/// 读取请求体，先按 Content-Length 拒绝过大的请求，读取时再按实际大小检查
+async fn read_body(request: Request<Body>) -> Result<Bytes, Response<Body>> {
+    let too_large = || error_response(StatusCode::PAYLOAD_TOO_LARGE, "bad_request", "request body too large");
+    let length = request
+        .headers()
+        .get(header::CONTENT_LENGTH)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.parse::<u64>().ok());
+    if length.is_some_and(|length| length > MAX_BODY_SIZE as u64) {
+        return Err(too_large());
+    }
+    let mut body = request.into_body();
+    let mut bytes = Vec::new();
+    while let Some(chunk) = body.data().await {
+        let chunk = chunk.map_err(|e| error_response(StatusCode::BAD_REQUEST, "bad_request", &e.to_string()))?;
+        if bytes.len() + chunk.len() > MAX_BODY_SIZE {
+            return Err(too_large());
+        }
+        bytes.extend_from_slice(&chunk);
+    }
+    Ok(Bytes::from(bytes))
+}
+
+/// 服务端问题或密钥问题时换用备用模型，请求本身的问题换模型也无法解决
+fn should_fall_back(error: &anyhow::Error) -> bool {
+    match error.downcast_ref::<ProviderError>() {
+        Some(error) => error.is_retryable() || matches!(error, ProviderError::Auth { .. }),
+        None => true,
+    }
+}
+
+impl State {
+    fn authorized(&self, request: &Request<Body>) -> bool {
+        let Some(token) = &self.options.token else {
+            return false;
+        };
+        request
+            .headers()
+            .get(header::AUTHORIZATION)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.strip_prefix("Bearer "))
+            .is_some_and(|value| constant_time_eq(value, token))
+    }
+
+    fn record(&self, model: &str, usage: Option<&Usage>, failed: bool) {
+        let mut totals = self.usage.lock().unwrap_or_else(|e| e.into_inner());
+        let total = totals.entry(model.to_string()).or_default();
+        total.requests += 1;
+        if failed {
+            total.failures += 1;
+        }
+        if let Some(usage) = usage {
+            total.prompt_tokens += usage.prompt_tokens;
+            total.completion_tokens += usage.completion_tokens;
+        }
+    }
+
+    fn models(&self) -> Response<Body> {
+        let created = unix_time();
+        let data: Vec<serde_json::Value> = self
+            .config
+            .model_names()
+            .into_iter()
+            .chain(self.bots.bot_names().into_iter().map(|name| format!("{}{}", BOT_PREFIX, name)))
+            .map(|id| serde_json::json!({"id": id, "object": "model", "created": created, "owned_by": "gpt-shell"}))
+            .collect();
+        json_response(StatusCode::OK, &serde_json::json!({"object": "list", "data": data}))
+    }
+
+    fn usage_report(&self) -> Response<Body> {
+        let totals = self.usage.lock().unwrap_or_else(|e| e.into_inner());
+        let data: serde_json::Map<String, serde_json::Value> = totals
+            .iter()
+            .map(|(model, total)| {
+                (
+                    model.clone(),
+                    serde_json::json!({
+                        "requests": total.requests,
+                        "failures": total.failures,
+                        "prompt_tokens": total.prompt_tokens,
+                        "completion_tokens": total.completion_tokens,
+                    }),
+                )
+            })
+            .collect();
+        json_response(StatusCode::OK, &serde_json::Value::Object(data))
+    }
+
+    /// 按名称查找模型：先按配置中的名称，再按服务端的模型 id
+    fn find_model(&self, name: &str) -> Option<(String, &ModelConfig)> {
+        if let Some(model) = self.config.get_model(name) {
+            return Some((name.to_string(), model));
+        }
+        self.config
+            .model_names()
+            .into_iter()
+            .find_map(|key| self.config.get_model(&key).filter(|model| model.model == name).map(|model| (key, model)))
+    }
+
+    /// 解析请求的模型名称，得到机器人以及依次尝试的模型
+    fn resolve(&self, requested: Option<&str>) -> Result<Route<'_>, String> {
+        let requested = requested.filter(|name| !name.is_empty() && *name != "default");
+        let (bot, name) = match requested.and_then(|name| name.strip_prefix(BOT_PREFIX)) {
+            Some(bot_name) => {
+                let bot = self
+                    .bots
+                    .get_bot(bot_name)
+                    .ok_or_else(|| format!("bot not found: {}", bot_name))?;
+                (Some(bot), bot.model.as_deref())
+            }
+            None => (None, requested),
+        };
+        let primary = match name {
+            Some(name) => self
+                .find_model(name)
+                .ok_or_else(|| format!("model not found: {}", name))?,
+            None => self
+                .config
+                .get_current_model()
+                .map(|(name, model)| (name.to_string(), model))
+                .ok_or_else(|| "no model configured".to_string())?,
+        };
+        let mut models = vec![primary];
+        for name in &self.options.fallback {
+            if let Some(model) = self.config.get_model(name) {
+                if !models.iter().any(|(existing, _)| existing == name) {
+                    models.push((name.clone(), model));
+                }
+            }
+        }
+        Ok(Route { bot, models })
+    }
+
+    /// 浏览器中的网页也能向本机地址发请求：带 Origin 的请求一律拒绝，
+    /// 只监听本机地址时还要求 Host 是本机，POST 请求必须是 JSON，避免无需预检的 text/plain 请求
+    async fn handle(self: Arc<Self>, request: Request<Body>) -> Response<Body> {
+        let headers = request.headers();
+        if headers.contains_key(header::ORIGIN) {
+            return error_response(StatusCode::FORBIDDEN, "forbidden", "cross-origin requests are not allowed");
+        }
+        let host = headers.get(header::HOST).and_then(|value| value.to_str().ok());
+        if self.options.addr.ip().is_loopback() && !host.is_some_and(is_loopback_host) {
+            return error_response(StatusCode::FORBIDDEN, "forbidden", "the Host header must be a loopback address");
+        }
+        let json = headers
+            .get(header::CONTENT_TYPE)
+            .and_then(|value| value.to_str().ok())
+            .is_some_and(|value| value.split(';').next().is_some_and(|mime| mime.trim().eq_ignore_ascii_case("application/json")));
+        if request.method() == Method::POST && !json {
+            return error_response(StatusCode::UNSUPPORTED_MEDIA_TYPE, "bad_request", "Content-Type must be application/json");
+        }
+        if !self.authorized(&request) {
+            return error_response(StatusCode::UNAUTHORIZED, "auth", "invalid or missing bearer token");
+        }
+        match (request.method(), request.uri().path().trim_end_matches('/')) {
+            (&Method::GET, "/v1/models") => self.models(),
+            (&Method::GET, "/v1/usage") => self.usage_report(),
+            (&Method::POST, "/v1/chat/completions") => self.chat(request).await,
+            _ => error_response(StatusCode::NOT_FOUND, "not_found", "unknown endpoint"),
+        }
+    }
+
+    async fn chat(self: Arc<Self>, request: Request<Body>) -> Response<Body> {
+        let start = Instant::now();
+        let body = match read_body(request).await {
+            Ok(body) => body,
+            Err(response) => return response,
+        };
+        let chat: ChatRequest = match serde_json::from_slice(&body) {
+            Ok(chat) => chat,
+            Err(e) => return error_response(StatusCode::BAD_REQUEST, "bad_request", &format!("invalid request: {}", e)),
+        };
+        let requested = chat.model.clone().unwrap_or_default();
+        let Route { bot, models } = match self.resolve(chat.model.as_deref()) {
+            Ok(route) => route,
+            Err(message) => return error_response(StatusCode::NOT_FOUND, "model_not_found", &message),
+        };
+
+        let mut messages: Vec<Message> = match chat.messages.into_iter().map(ClientMessage::into_message).collect() {
+            Ok(messages) => messages,
+            Err(message) => return error_response(StatusCode::BAD_REQUEST, "bad_request", &message),
+        };
+        if let Some(bot) = bot {
+            let ctx = TemplateContext::default();
+            let mut initial = match bot.initial_messages(&ctx) {
+                Ok(initial) => initial,
+                Err(e) => return error_response(StatusCode::INTERNAL_SERVER_ERROR, "bot", &format!("{:#}", e)),
+            };
+            if let Some(last) = messages.iter_mut().rev().find(|message| message.role == "user") {
+                match bot.render_input(&last.content, &ctx) {
+                    Ok(content) => last.content = content,
+                    Err(e) => return error_response(StatusCode::BAD_REQUEST, "bot", &format!("{:#}", e)),
+                }
+            }
+            initial.append(&mut messages);
+            messages = initial;
+        }
+
+        let mut last_error = None;
+        for (name, model) in &models {
+            let mut params = model.params.clone();
+            if let Some(bot) = bot {
+                params = params.merge(&bot.params);
+            }
+            let provider = match self.config.provider(model) {
+                Ok(provider) => provider.with_params(params.merge(&chat.params)),
+                Err(e) => {
+                    last_error = Some(e);
+                    continue;
+                }
+            };
+            let provider = apply_response_format(provider, chat.response_format.as_ref(), bot);
+            match provider.chat(messages.clone(), chat.stream, self.running.clone()).await {
+                Ok(events) => {
+                    let id = format!("chatcmpl-gpt-shell-{}", self.next_id.fetch_add(1, Ordering::SeqCst));
+                    let reply = Reply {
+                        id,
+                        model: if requested.is_empty() { name.clone() } else { requested.clone() },
+                        upstream: name.clone(),
+                        start,
+                    };
+                    return if chat.stream {
+                        self.stream_response(reply, events)
+                    } else {
+                        self.json_reply(reply, events).await
+                    };
+                }
+                Err(e) => {
+                    eprintln!("{} {} {}: {:#}", "POST /v1/chat/completions".dimmed(), name, "failed".red(), e);
+                    self.record(name, None, true);
+                    let fall_back = should_fall_back(&e);
+                    last_error = Some(e);
+                    if !fall_back {
+                        break;
+                    }
+                }
+            }
+        }
+        match last_error {
+            Some(e) => match e.downcast_ref::<ProviderError>() {
+                Some(error) => provider_error_response(error),
+                None => error_response(StatusCode::BAD_GATEWAY, "error", &format!("{:#}", e)),
+            },
+            None => error_response(StatusCode::NOT_FOUND, "model_not_found", "no model available"),
+        }
+    }
+
+    fn log(&self, reply: &Reply, usage: Option<&Usage>, failed: bool) {
+        self.record(&reply.upstream, usage, failed);
+        let tokens = usage
+            .map(|usage| format!(" {}+{} tokens", usage.prompt_tokens, usage.completion_tokens))
+            .unwrap_or_default();
+        let status = if failed { "error".red() } else { "ok".green() };
+        eprintln!(
+            "{} {} -> {} {} {}ms{}",
+            "POST /v1/chat/completions".dimmed(),
+            reply.model,
+            reply.upstream,
+            status,
+            reply.start.elapsed().as_millis(),
+            tokens
+        );
+    }
+
+    async fn json_reply(&self, reply: Reply, mut events: ChatStream) -> Response<Body> {
+        let mut content = String::new();
+        let mut reasoning = String::new();
+        let mut finish = None;
+        let mut usage = None;
+        while let Some(event) = events.next().await {
+            match event {
+                ChatEvent::TextDelta(text) => content.push_str(&text),
+                ChatEvent::ReasoningDelta(text) => reasoning.push_str(&text),
+                ChatEvent::Finish(reason) => finish = Some(reason.to_string()),
+                ChatEvent::Usage(value) => usage = Some(value),
+                ChatEvent::Error(e) => {
+                    self.log(&reply, None, true);
+                    return provider_error_response(&e);
+                }
+                ChatEvent::ToolCallDelta(_) => {}
+            }
+        }
+        self.log(&reply, usage.as_ref(), false);
+
+        let mut message = serde_json::json!({"role": "assistant", "content": content});
+        if !reasoning.is_empty() {
+            message["reasoning_content"] = serde_json::json!(reasoning);
+        }
+        let mut body = serde_json::json!({
+            "id": reply.id,
+            "object": "chat.completion",
+            "created": unix_time(),
+            "model": reply.model,
+            "choices": [{"index": 0, "message": message, "finish_reason": finish.unwrap_or_else(|| "stop".to_string())}],
+        });
+        if let Some(usage) = usage {
+            body["usage"] = serde_json::to_value(&usage).unwrap_or_default();
+        }
+        json_response(StatusCode::OK, &body)
+    }
+
+    /// 以 SSE 格式转发回复流，结束后记录用量
+    fn stream_response(self: Arc<Self>, reply: Reply, mut events: ChatStream) -> Response<Body> {
+        let (mut sender, body) = Body::channel();
+        tokio::spawn(async move {
+            let created = unix_time();
+            let chunk = |delta: serde_json::Value, finish: Option<String>| {
+                let value = serde_json::json!({
+                    "id": reply.id,
+                    "object": "chat.completion.chunk",
+                    "created": created,
+                    "model": reply.model,
+                    "choices": [{"index": 0, "delta": delta, "finish_reason": finish}],
+                });
+                Bytes::from(format!("data: {}\n\n", value))
+            };
+            let mut usage = None;
+            let mut failed = false;
+            if sender.send_data(chunk(serde_json::json!({"role": "assistant"}), None)).await.is_err() {
+                return;
+            }
+            while let Some(event) = events.next().await {
+                let data = match event {
+                    ChatEvent::TextDelta(text) => chunk(serde_json::json!({"content": text}), None),
+                    ChatEvent::ReasoningDelta(text) => chunk(serde_json::json!({"reasoning_content": text}), None),
+                    ChatEvent::Finish(reason) => chunk(serde_json::json!({}), Some(reason.to_string())),
+                    ChatEvent::Usage(value) => {
+                        usage = Some(value);
+                        continue;
+                    }
+                    ChatEvent::Error(e) => {
+                        failed = true;
+                        let error = serde_json::json!({"error": {"message": e.to_string(), "type": e.kind()}});
+                        Bytes::from(format!("data: {}\n\n", error))
+                    }
+                    ChatEvent::ToolCallDelta(_) => continue,
+                };
+                // 客户端断开连接
+                if sender.send_data(data).await.is_err() {
+                    break;
+                }
+            }
+            if let Some(usage) = &usage {
+                let value = serde_json::json!({
+                    "id": reply.id,
+                    "object": "chat.completion.chunk",
+                    "created": created,
+                    "model": reply.model,
+                    "choices": [],
+                    "usage": usage,
+                });
+                let _ = sender.send_data(Bytes::from(format!("data: {}\n\n", value))).await;
+            }
+            let _ = sender.send_data(Bytes::from("data: [DONE]\n\n")).await;
+            self.log(&reply, usage.as_ref(), failed);
+        });
+        Response::builder()
+            .header(header::CONTENT_TYPE, "text/event-stream")
+            .header(header::CACHE_CONTROL, "no-cache")
+            .body(body)
+            .unwrap_or_default()
+    }
+}
+
+/// 请求对应的机器人与模型，第一个模型失败时依次尝试后面的备用模型
+struct Route<'a> {
+    bot: Option<&'a Bot>,
+    models: Vec<(String, &'a ModelConfig)>,
+}
+
+/// 一次回复的标识与计时
+struct Reply {
+    id: String,
+    /// 返回给客户端的模型名称
+    model: String,
+    /// 实际使用的模型
+    upstream: String,
+    start: Instant,
+}
+
+/// 将客户端的 response_format 转换为 provider 设置，未指定时使用机器人的输出设置
+fn apply_response_format(provider: Provider, format: Option<&serde_json::Value>, bot: Option<&Bot>) -> Provider {
+    match format.and_then(|format| format["type"].as_str()) {
+        Some("json_object") => provider.with_json_mode(true),
+        Some("json_schema") => {
+            let schema = format.map(|format| &format["json_schema"]);
+            let name = schema.and_then(|schema| schema["name"].as_str()).unwrap_or("output");
+            match schema.map(|schema| OutputSchema::new(name, schema["schema"].clone())) {
+                Some(Ok(schema)) => provider.with_output_schema(Some(schema)),
+                _ => provider.with_json_mode(true),
+            }
+        }
+        Some(_) => provider,
+        None => match bot {
+            Some(bot) => provider
+                .with_json_mode(bot.output_format == crate::bots::OutputFormat::Json)
+                .with_output_schema(bot.output_schema().ok().flatten()),
+            None => provider,
+        },
+    }
+}
+
+/// 启动兼容 OpenAI 接口的本地服务，Ctrl+C 后退出并输出用量统计
+pub async fn run(config: Config, bots: BotsConfig, mut options: ServeOptions, running: Arc<AtomicBool>) -> Result<()> {
+    for name in &options.fallback {
+        if config.get_model(name).is_none() {
+            return Err(anyhow::anyhow!("fallback model not found: {}", name));
+        }
+    }
+    let generated = options.token.is_none();
+    let token = options.token.get_or_insert_with(random_token).clone();
+    let addr = options.addr;
+    let state = Arc::new(State {
+        config,
+        bots,
+        options,
+        running: running.clone(),
+        usage: Mutex::new(BTreeMap::new()),
+        next_id: AtomicU64::new(1),
+    });
+
+    let service_state = state.clone();
+    let make_service = make_service_fn(move |_| {
+        let state = service_state.clone();
+        async move {
+            Ok::<_, Infallible>(service_fn(move |request| {
+                let state = state.clone();
+                async move { Ok::<_, Infallible>(state.handle(request).await) }
+            }))
+        }
+    });
+    let server = hyper::Server::try_bind(&addr)
+        .map_err(|e| anyhow::anyhow!("can not listen on {}: {}", addr, e))?
+        .serve(make_service);
+    println!("serving OpenAI-compatible API on http://{}/v1 (press Ctrl+C to stop)", addr);
+    if generated {
+        println!("api key: {} (generated for this run, pass --token to choose one)", token.green());
+    }
+
+    server
+        .with_graceful_shutdown(async move {
+            while running.load(Ordering::SeqCst) {
+                tokio::time::sleep(Duration::from_millis(100)).await;
+            }
+        })
+        .await?;
+
+    let totals = state.usage.lock().unwrap_or_else(|e| e.into_inner());
+    if !totals.is_empty() {
+        println!();
+        for (model, total) in totals.iter() {
+            println!(
+                "{}: {} request(s), {} failed, {} prompt + {} completion tokens",
+                model.green(),
+                total.requests,
+                total.failures,
+                total.prompt_tokens,
+                total.completion_tokens
+            );
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn message(value: serde_json::Value) -> Result<Message, String> {
+        serde_json::from_value::<ClientMessage>(value).unwrap().into_message()
+    }
+
+    #[test]
+    fn text_parts_are_joined() {
+        let parsed = message(serde_json::json!({
+            "role": "user",
+            "content": [{"type": "text", "text": "a"}, {"type": "image_url"}, {"type": "text", "text": "b"}]
+        }))
+        .unwrap();
+        assert_eq!(parsed.content, "a\nb");
+    }
+
+    #[test]
+    fn tool_messages_are_rejected() {
+        assert!(message(serde_json::json!({"role": "tool", "tool_call_id": "1", "content": "x"})).is_err());
+        assert!(message(serde_json::json!({"role": "assistant", "tool_calls": [{"id": "1"}]})).is_err());
+        assert!(message(serde_json::json!({"role": "assistant", "content": "a", "tool_calls": []})).is_ok());
+    }
+
+    #[test]
+    fn only_loopback_hosts_are_accepted() {
+        for host in ["localhost", "LOCALHOST:8080", "127.0.0.1:8080", "127.1.2.3", "[::1]:8080", "[::1]"] {
+            assert!(is_loopback_host(host), "{}", host);
+        }
+        for host in ["evil.example:8080", "localhost.evil.example", "192.168.1.2:8080", "[::2]:80", ""] {
+            assert!(!is_loopback_host(host), "{}", host);
+        }
+    }
+
+    #[test]
+    fn generated_tokens_differ() {
+        let token = random_token();
+        assert_eq!(token.len(), 32);
+        assert_ne!(token, random_token());
+    }
+
+    #[test]
+    fn tokens_compare_by_value() {
+        assert!(constant_time_eq("secret", "secret"));
+        assert!(!constant_time_eq("secret", "secreT"));
+        assert!(!constant_time_eq("secret", "secret2"));
+    }
+}
@@ -0,0 +1,133 @@
+use crate::agents;
+use crate::config::Config;
+use crate::llm_provider::{self, LLMProvider, Message};
+use anyhow::Result;
+use futures::StreamExt;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+/// 支持集成的 shell
+pub const SHELLS: &[&str] = &["bash", "zsh", "fish"];
+
+const BASH_INIT: &str = r#"# gpt-shell: press Ctrl+G to turn the command line into a command
+_gpt_suggest() {
+    [ -n "$READLINE_LINE" ] || return
+    local result
+    result=$(gpt suggest --shell bash -- "$READLINE_LINE") || return
+    READLINE_LINE=$result
+    READLINE_POINT=${#READLINE_LINE}
+}
+bind -x '"\C-g": _gpt_suggest'
+"#;
+
+const ZSH_INIT: &str = r#"# gpt-shell: press Ctrl+G to turn the command line into a command
+_gpt_suggest() {
+    [[ -n $BUFFER ]] || return
+    local result
+    zle -I
+    if result=$(gpt suggest --shell zsh -- "$BUFFER"); then
+        BUFFER=$result
+        CURSOR=${#BUFFER}
+    fi
+    zle reset-prompt
+}
+zle -N _gpt_suggest
+bindkey '^G' _gpt_suggest
+"#;
+
+const FISH_INIT: &str = r#"# gpt-shell: press Ctrl+G to turn the command line into a command
+function _gpt_suggest
+    set -l line (commandline)
+    test -n "$line"; or return
+    set -l result (gpt suggest --shell fish -- "$line" | string collect)
+    and commandline -r -- $result
+    commandline -f repaint
+end
+bind \cg _gpt_suggest
+"#;
+
+/// 绑定快捷键的 shell 脚本，需要在 shell 的启动文件中加载
+pub fn init_script(shell: &str) -> Result<&'static str> {
+    match shell {
+        "bash" => Ok(BASH_INIT),
+        "zsh" => Ok(ZSH_INIT),
+        "fish" => Ok(FISH_INIT),
+        other => Err(anyhow::anyhow!("unsupported shell: {}, expected one of {}", other, SHELLS.join(", "))),
+    }
+}
+
+/// 生成命令的系统提示词，操作系统部分与 agent 共用
+fn suggest_prompt(shell: Option<&str>) -> String {
+    let mut prompt = String::from("你是一个命令行专家，需要把用户的描述转换为一条可以直接在终端执行的命令。\n");
+    prompt.push_str(&agents::platform_prompt(shell));
+    if let Ok(cwd) = std::env::current_dir() {
+        prompt.push_str(&format!("当前目录：{}\n", cwd.display()));
+    }
+    prompt.push_str(
+        "\n要求：\n\
+         1. 只输出命令本身，不要解释，不要使用代码块\n\
+         2. 需要多个步骤时，用管道或 && 组合成一行\n\
+         3. 如果输入已经是命令，修正其中的错误或按描述补全\n\
+         4. 无法确定的参数使用 <占位符>\n",
+    );
+    prompt
+}
+
+/// 去掉模型回复中多余的代码块标记与提示符
+fn clean_command(reply: &str) -> String {
+    let mut command = reply.trim();
+    if let Some(body) = command.strip_prefix("```") {
+        // 跳过代码块的语言标记
+        let body = body.split_once('\n').map(|(_, rest)| rest).unwrap_or_default();
+        command = body.trim_end().strip_suffix("```").unwrap_or(body).trim();
+    }
+    command.strip_prefix("$ ").unwrap_or(command).to_string()
+}
+
+/// 根据描述生成命令并输出到 stdout，供 shell 集成替换当前命令行
+/// 命令不会被执行，出错或被取消时返回 Err，shell 中的原有内容保持不变
+pub async fn suggest(
+    config: &Config,
+    model: Option<&str>,
+    text: &str,
+    shell: Option<&str>,
+    running: Arc<AtomicBool>,
+) -> Result<()> {
+    let model_config = match model {
+        Some(name) => config
+            .get_model(name)
+            .ok_or_else(|| anyhow::anyhow!("model not found: {}", name))?,
+        None => config
+            .get_current_model()
+            .map(|(_, model)| model)
+            .ok_or_else(|| anyhow::anyhow!("no model configured, add one with `gpt config model add`"))?,
+    };
+    // 再次按下快捷键时应该得到新的建议，不使用缓存
+    let provider = config.provider(model_config)?.with_cache(None);
+    let messages = vec![
+        Message::new("system", suggest_prompt(shell)),
+        Message::new("user", text.to_string()),
+    ];
+
+    let events = tokio::select! {
+        result = provider.chat(messages, false, running.clone()) => result?,
+        _ = async {
+            while running.load(Ordering::SeqCst) {
+                tokio::time::sleep(Duration::from_millis(50)).await;
+            }
+        } => return Err(anyhow::anyhow!("cancelled")),
+    };
+    let mut chunks = llm_provider::text_stream(events);
+    let mut reply = String::new();
+    while let Some(chunk) = chunks.next().await {
+        reply.push_str(&chunk?);
+    }
+
+    let command = clean_command(&reply);
+    if command.is_empty() {
+        return Err(anyhow::anyhow!("the model did not return a command"));
+    }
+    println!("{}", command);
+    Ok(())
+}
@@ -0,0 +1,331 @@
+use anyhow::Result;
+use std::collections::HashMap;
+use std::env;
+use std::process::Command;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// 只能在 `user_template` 中使用的变量，系统提示词在读取用户输入之前就要渲染
+pub const INPUT_VAR: &str = "input";
+
+/// 环境变量前缀，例如 `GPT_VAR_LANG` 对应 `{{lang}}`
+const ENV_PREFIX: &str = "GPT_VAR_";
+
+/// `{{env.NAME}}` 可以读取的环境变量，以及所有 `GPT_VAR_` 开头的变量
+/// 模板可能来自 registry 或项目配置，不能读取 API Key、令牌等任意环境变量
+const ENV_ALLOWLIST: &[&str] = &["USER", "USERNAME", "HOME", "SHELL", "LANG", "TERM", "EDITOR", "TZ"];
+
+fn env_allowed(key: &str) -> bool {
+    ENV_ALLOWLIST.contains(&key) || key.starts_with(ENV_PREFIX)
+}
+
+/// 模板变量上下文
+/// 变量查找顺序：命令行 `--var` > 环境变量 `GPT_VAR_<NAME>` > 内置变量
+/// 创建时只复制允许读取的环境变量，渲染过程不再访问进程环境
+#[derive(Debug, Clone)]
+pub struct TemplateContext {
+    vars: HashMap<String, String>,
+    env: HashMap<String, String>,
+}
+
+impl Default for TemplateContext {
+    fn default() -> Self {
+        Self::new(HashMap::new())
+    }
+}
+
+impl TemplateContext {
+    pub fn new(vars: HashMap<String, String>) -> Self {
+        let env = env::vars().filter(|(key, _)| env_allowed(key)).collect();
+        Self { vars, env }
+    }
+
+    /// 设置用户输入，对应 `{{input}}`
+    pub fn with_input(mut self, input: &str) -> Self {
+        self.vars.insert("input".to_string(), input.to_string());
+        self
+    }
+
+    /// 设置单个变量，覆盖同名的命令行变量
+    pub fn with_var(mut self, name: &str, value: &str) -> Self {
+        self.vars.insert(name.to_string(), value.to_string());
+        self
+    }
+
+    fn resolve(&self, name: &str) -> Option<String> {
+        if let Some(value) = self.vars.get(name) {
+            return Some(value.clone());
+        }
+        if let Some(key) = name.strip_prefix("env.") {
+            return self.env.get(key).cloned();
+        }
+        if let Some(value) = self.env.get(&format!("{}{}", ENV_PREFIX, name.to_uppercase())) {
+            return Some(value.clone());
+        }
+        builtin(name)
+    }
+}
+
+/// 内置变量，仅在模板引用时才会计算
+fn builtin(name: &str) -> Option<String> {
+    match name {
+        "cwd" => env::current_dir().ok().map(|p| p.display().to_string()),
+        "date" => Some(today()),
+        "os" => Some(env::consts::OS.to_string()),
+        "git_branch" => command_output("git", &["rev-parse", "--abbrev-ref", "HEAD"]),
+        "clipboard" => clipboard(),
+        _ => None,
+    }
+}
+
+fn command_output(program: &str, args: &[&str]) -> Option<String> {
+    let output = Command::new(program).args(args).output().ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    Some(String::from_utf8_lossy(&output.stdout).trim_end().to_string())
+}
+
+fn clipboard() -> Option<String> {
+    if cfg!(target_os = "windows") {
+        command_output("powershell", &["-Command", "Get-Clipboard"])
+    } else if cfg!(target_os = "macos") {
+        command_output("pbpaste", &[])
+    } else {
+        command_output("wl-paste", &["--no-newline"])
+            .or_else(|| command_output("xclip", &["-selection", "clipboard", "-o"]))
+            .or_else(|| command_output("xsel", &["--clipboard", "--output"]))
+    }
+}
+
+/// 本地时区的当前日期，格式为 YYYY-MM-DD
+fn today() -> String {
+    let secs = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs() as i64)
+        .unwrap_or(0);
+    date_at(secs, utc_offset(secs))
+}
+
+/// 本地时区相对 UTC 的偏移秒数，由 libc 按 TZ 与系统时区计算，包含夏令时
+#[cfg(unix)]
+fn utc_offset(secs: i64) -> i64 {
+    let time = secs as libc::time_t;
+    let mut tm: libc::tm = unsafe { std::mem::zeroed() };
+    // localtime_r 是线程安全版本，失败时返回空指针
+    if unsafe { libc::localtime_r(&time, &mut tm) }.is_null() {
+        return 0;
+    }
+    tm.tm_gmtoff as i64
+}
+
+#[cfg(not(unix))]
+fn utc_offset(_secs: i64) -> i64 {
+    0
+}
+
+/// 指定 UTC 时间戳在给定时区偏移下的日期
+fn date_at(secs: i64, offset: i64) -> String {
+    // 按公历将天数换算为年月日
+    let days = (secs + offset).div_euclid(86_400) + 719_468;
+    let era = days.div_euclid(146_097);
+    let day_of_era = days.rem_euclid(146_097);
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let mp = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+    format!("{:04}-{:02}-{:02}", year, month, day)
+}
+
+/// 渲染模板
+/// 支持 `{{name}}`、带默认值的 `{{name|default}}`、`{{env.NAME}}`（仅限允许的环境变量），
+/// `\{{` 输出字面量 `{{`。所有缺失的变量会在一个错误中列出
+pub fn render(template: &str, ctx: &TemplateContext) -> Result<String> {
+    let mut output = String::with_capacity(template.len());
+    let mut missing = Vec::new();
+    let mut rest = template;
+
+    while let Some(start) = rest.find("{{") {
+        if rest[..start].ends_with('\\') {
+            output.push_str(&rest[..start - 1]);
+            output.push_str("{{");
+            rest = &rest[start + 2..];
+            continue;
+        }
+        output.push_str(&rest[..start]);
+        let after = &rest[start + 2..];
+        let end = after.find("}}").ok_or_else(|| {
+            let offset = template.len() - rest.len() + start;
+            anyhow::anyhow!("unclosed '{{{{' at offset {} in template", offset)
+        })?;
+
+        let expr = &after[..end];
+        let (name, default) = match expr.split_once('|') {
+            Some((name, default)) => (name.trim(), Some(default.trim())),
+            None => (expr.trim(), None),
+        };
+        if name.is_empty() {
+            return Err(anyhow::anyhow!("empty variable name in template"));
+        }
+        if let Some(key) = name.strip_prefix("env.").filter(|key| !env_allowed(key)) {
+            return Err(anyhow::anyhow!(
+                "environment variable {} can not be used in templates, export it as {}{} and use {{{{{}}}}} instead",
+                key,
+                ENV_PREFIX,
+                key.to_uppercase(),
+                key.to_lowercase()
+            ));
+        }
+
+        match ctx.resolve(name).or_else(|| default.map(str::to_string)) {
+            Some(value) => output.push_str(&value),
+            None => {
+                if !missing.contains(&name) {
+                    missing.push(name);
+                }
+            }
+        }
+        rest = &after[end + 2..];
+    }
+    output.push_str(rest);
+
+    if !missing.is_empty() {
+        return Err(anyhow::anyhow!(
+            "missing template variables: {} (pass them with --var key=value or set {}<NAME>)",
+            missing.join(", "),
+            ENV_PREFIX
+        ));
+    }
+    Ok(output)
+}
+
+/// 模板是否引用了指定变量，转义的 `\{{` 不算引用
+pub fn uses_variable(template: &str, variable: &str) -> bool {
+    let mut rest = template;
+    while let Some(start) = rest.find("{{") {
+        let after = &rest[start + 2..];
+        if !rest[..start].ends_with('\\') {
+            let Some(end) = after.find("}}") else {
+                return false;
+            };
+            let expr = &after[..end];
+            let name = expr.split_once('|').map_or(expr, |(name, _)| name);
+            if name.trim() == variable {
+                return true;
+            }
+        }
+        rest = after;
+    }
+    false
+}
+
+/// 解析命令行的 `key=value` 变量
+pub fn parse_var(s: &str) -> Result<(String, String), String> {
+    match s.split_once('=') {
+        Some((key, value)) if !key.trim().is_empty() => {
+            Ok((key.trim().to_string(), value.to_string()))
+        }
+        _ => Err(format!("invalid variable '{}', expected key=value", s)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ctx(vars: &[(&str, &str)]) -> TemplateContext {
+        ctx_with_env(vars, &[])
+    }
+
+    fn ctx_with_env(vars: &[(&str, &str)], env: &[(&str, &str)]) -> TemplateContext {
+        let pairs = |items: &[(&str, &str)]| items.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect();
+        TemplateContext { vars: pairs(vars), env: pairs(env) }
+    }
+
+    #[test]
+    fn renders_variables_and_defaults() {
+        let ctx = ctx(&[("lang", "French")]);
+        assert_eq!(render("Translate to {{lang}}", &ctx).unwrap(), "Translate to French");
+        assert_eq!(render("{{ lang | English }}", &ctx).unwrap(), "French");
+        assert_eq!(render("{{tone|polite}} reply", &ctx).unwrap(), "polite reply");
+        assert_eq!(render("no placeholders", &ctx).unwrap(), "no placeholders");
+    }
+
+    #[test]
+    fn escaped_braces_are_literal() {
+        let ctx = ctx(&[("name", "x")]);
+        assert_eq!(render(r"\{{name}} is {{name}}", &ctx).unwrap(), "{{name}} is x");
+        assert_eq!(render(r"\\{{name}}", &ctx).unwrap(), r"\{{name}}");
+    }
+
+    #[test]
+    fn reports_all_missing_variables_once() {
+        let error = render("{{a}} {{b}} {{a}}", &ctx(&[])).unwrap_err().to_string();
+        assert!(error.contains("missing template variables: a, b"), "{}", error);
+    }
+
+    #[test]
+    fn rejects_malformed_templates() {
+        assert!(render("{{name", &ctx(&[("name", "x")])).unwrap_err().to_string().contains("unclosed"));
+        assert!(render("{{ }}", &ctx(&[])).is_err());
+    }
+
+    #[test]
+    fn resolution_order() {
+        let env = [("GPT_VAR_TPL_ORDER", "from env"), ("GPT_VAR_OS", "from env")];
+        assert_eq!(render("{{tpl_order}}", &ctx_with_env(&[], &env)).unwrap(), "from env");
+        assert_eq!(
+            render("{{tpl_order}}", &ctx_with_env(&[("tpl_order", "from cli")], &env)).unwrap(),
+            "from cli"
+        );
+        assert_eq!(render("{{os}}", &ctx_with_env(&[], &env)).unwrap(), "from env");
+        assert_eq!(render("{{os}}", &ctx(&[])).unwrap(), env::consts::OS);
+    }
+
+    #[test]
+    fn env_is_limited_to_allowlist() {
+        let ctx = ctx_with_env(&[], &[("GPT_VAR_TPL_ENV", "visible")]);
+        assert_eq!(render("{{env.GPT_VAR_TPL_ENV}}", &ctx).unwrap(), "visible");
+
+        let error = render("{{env.TPL_TEST_API_KEY}}", &ctx).unwrap_err().to_string();
+        assert!(error.contains("can not be used in templates"), "{}", error);
+    }
+
+    #[test]
+    fn finds_variable_references() {
+        assert!(uses_variable("Reply to {{ input | hi }}", INPUT_VAR));
+        assert!(!uses_variable(r"\{{input}} {{inputs}}", INPUT_VAR));
+    }
+
+    #[test]
+    fn input_and_with_var_override_cli_vars() {
+        let ctx = ctx(&[("input", "old"), ("lang", "en")]).with_input("new").with_var("lang", "de");
+        assert_eq!(render("{{input}} {{lang}}", &ctx).unwrap(), "new de");
+    }
+
+    #[test]
+    fn today_is_iso_date() {
+        let date = today();
+        assert_eq!(date.len(), 10);
+        assert_eq!(&date[4..5], "-");
+        assert_eq!(&date[7..8], "-");
+    }
+
+    #[test]
+    fn date_follows_the_utc_offset() {
+        // 2024-02-29 20:30 UTC
+        let secs = 1_709_238_600;
+        assert_eq!(date_at(secs, 0), "2024-02-29");
+        assert_eq!(date_at(secs, 8 * 3600), "2024-03-01");
+        assert_eq!(date_at(secs, -21 * 3600), "2024-02-28");
+        assert_eq!(date_at(0, -3600), "1969-12-31");
+    }
+
+    #[test]
+    fn parses_cli_vars() {
+        assert_eq!(parse_var("lang=de=x"), Ok(("lang".to_string(), "de=x".to_string())));
+        assert!(parse_var("=x").is_err());
+        assert!(parse_var("novalue").is_err());
+    }
+}
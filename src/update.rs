@@ -1,5 +1,4 @@
 use anyhow::Result;
-use reqwest;
 use std::fs;
 
 const RELEASES_URL: &str = "https://api.github.com/repos/wangenius/gpt-shell/releases/latest";
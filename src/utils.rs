@@ -1,8 +1,11 @@
 use anyhow::Result;
-use std::path::PathBuf;
-use std::fs;
+use colored::*;
+use std::fs::{self, File, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
 use std::env;
 use std::process::Command;
+use std::sync::atomic::{AtomicU64, Ordering};
 
 pub fn get_config_dir() -> Option<PathBuf> {
     let home = env::var("HOME").or_else(|_| env::var("USERPROFILE")).ok()?;
@@ -11,24 +14,104 @@ pub fn get_config_dir() -> Option<PathBuf> {
     Some(path)
 }
 
+/// 从当前目录向上查找项目级配置
+/// 返回找到的 `.gpt-shell/` 目录或 `gpt-shell.toml` 文件路径
+/// 用户级配置目录本身不会被当作项目配置
+pub fn find_project_config() -> Option<PathBuf> {
+    let cwd = env::current_dir().ok()?;
+    let user_dir = get_config_dir();
+    for dir in cwd.ancestors() {
+        let candidate = dir.join(".gpt-shell");
+        if candidate.is_dir() && Some(&candidate) != user_dir.as_ref() {
+            return Some(candidate);
+        }
+        let candidate = dir.join("gpt-shell.toml");
+        if candidate.is_file() {
+            return Some(candidate);
+        }
+    }
+    None
+}
+
+/// 原子写入文件：先写入同目录下的临时文件并 fsync，再重命名覆盖目标文件
+/// 并发的进程只会看到完整的旧文件或新文件
 pub fn save_file(content: &str, file_path: &PathBuf) -> Result<()> {
-    if let Some(parent) = file_path.parent() {
-        fs::create_dir_all(parent)?;
+    let parent = match file_path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent.to_path_buf(),
+        _ => PathBuf::from("."),
+    };
+    fs::create_dir_all(&parent)?;
+
+    let file_name = file_path
+        .file_name()
+        .map(|name| name.to_string_lossy().to_string())
+        .unwrap_or_default();
+    // 进程号加进程内计数，同一进程中并发写入同一文件也不会共用临时文件
+    static TMP_SEQ: AtomicU64 = AtomicU64::new(0);
+    let tmp_path = parent.join(format!(
+        ".{}.tmp.{}.{}",
+        file_name,
+        std::process::id(),
+        TMP_SEQ.fetch_add(1, Ordering::Relaxed)
+    ));
+
+    let result = (|| -> Result<()> {
+        let mut file = OpenOptions::new().write(true).create_new(true).open(&tmp_path)?;
+        // 保留原文件的权限，配置文件中可能包含 API Key
+        if let Ok(metadata) = fs::metadata(file_path) {
+            file.set_permissions(metadata.permissions())?;
+        }
+        file.write_all(content.as_bytes())?;
+        file.sync_all()?;
+        fs::rename(&tmp_path, file_path)?;
+        Ok(())
+    })();
+    if result.is_err() {
+        let _ = fs::remove_file(&tmp_path);
+    }
+    result?;
+
+    // 确保重命名本身也已落盘
+    #[cfg(unix)]
+    if let Ok(dir) = File::open(&parent) {
+        let _ = dir.sync_all();
     }
-    fs::write(file_path, content)?;
     Ok(())
 }
 
+/// 配置文件的建议性锁，释放时机为 guard 被丢弃
+pub struct FileLock {
+    _file: File,
+}
+
+/// 获取 `<file>.lock` 上的独占锁，其他 gpt 进程会等待锁释放
+/// 用于包住 读取-修改-保存 的整个过程
+pub fn lock_file(file_path: &Path) -> Result<FileLock> {
+    let mut lock_path = file_path.as_os_str().to_owned();
+    lock_path.push(".lock");
+    let lock_path = PathBuf::from(lock_path);
+    if let Some(parent) = lock_path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let file = OpenOptions::new()
+        .create(true)
+        .truncate(false)
+        .write(true)
+        .open(&lock_path)?;
+    file.lock()
+        .map_err(|e| anyhow::anyhow!("failed to lock {}: {}", lock_path.display(), e))?;
+    Ok(FileLock { _file: file })
+}
+
 pub fn open_file_in_editor(path: &PathBuf) -> Result<()> {
     if cfg!(windows) {
-        if Command::new("code").arg(path).spawn().is_err() {
-            if Command::new("C:\\Windows\\System32\\notepad.exe")
+        if Command::new("code").arg(path).spawn().is_err()
+            && Command::new("C:\\Windows\\System32\\notepad.exe")
                 .arg(path)
                 .spawn()
                 .is_err()
-            {
-                println!("can not open editor, the file path is: {}", path.display());
-            }
+        {
+            println!("can not open editor, the file path is: {}", path.display());
         }
     } else {
         if let Ok(editor) = env::var("EDITOR") {
@@ -48,4 +131,40 @@ pub fn open_file_in_editor(path: &PathBuf) -> Result<()> {
         }
     }
     Ok(())
-} 
\ No newline at end of file
+}
+
+/// 输出左对齐的表格，表头加粗，每个单元格可以指定颜色
+pub fn print_table(header: &[&str], rows: &[Vec<(String, Option<Color>)>]) {
+    let widths: Vec<usize> = header
+        .iter()
+        .enumerate()
+        .map(|(i, title)| {
+            rows.iter()
+                .map(|row| row[i].0.chars().count())
+                .chain([title.len()])
+                .max()
+                .unwrap_or(0)
+        })
+        .collect();
+
+    let line: Vec<String> = header
+        .iter()
+        .zip(&widths)
+        .map(|(title, width)| format!("{:<width$}", title, width = width).bold().to_string())
+        .collect();
+    println!("{}", line.join("  "));
+    for row in rows {
+        let line: Vec<String> = row
+            .iter()
+            .zip(&widths)
+            .map(|((text, color), width)| {
+                let text = format!("{:<width$}", text, width = width);
+                match color {
+                    Some(color) => text.color(*color).to_string(),
+                    None => text,
+                }
+            })
+            .collect();
+        println!("{}", line.join("  ").trim_end());
+    }
+}